@@ -0,0 +1,61 @@
+use bevy::utils::HashSet;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use bevy_tuto::plugins::map::definitions::{BiomeDefinitions, TileDefinitions};
+use bevy_tuto::plugins::map::generation::MapGenerationSettings;
+use bevy_tuto::plugins::map::map_config::MapConfig;
+use bevy_tuto::plugins::map::map_creator::Map;
+use bevy_tuto::plugins::map::pathfinding::{DijkstraMap, PathGrid, PathOptions};
+use bevy_tuto::world_seed::WorldSeed;
+
+const SEED: u64 = 42;
+
+// Full size map, as generated for a run.
+fn generate_map() -> Map {
+    let config = MapConfig::default();
+    let mut map = MapGenerationSettings::default()
+        .map_builder(WorldSeed(SEED), &config)
+        .build();
+    map.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+    map
+}
+
+// Walkable tiles closest to two opposite corners of the map.
+fn far_apart_tiles(map: &Map) -> ((i32, i32), (i32, i32)) {
+    let mut walkable = (0..map.height as i32)
+        .flat_map(|y| (0..map.width as i32).map(move |x| (x, y)))
+        .filter(|(x, y)| map.get_tileinfo_at(*x, *y).map_or(false, |tile_info| tile_info.walkable));
+    let first = walkable.next().expect("the map has walkable tiles");
+    let last = walkable.last().unwrap_or(first);
+    (first, last)
+}
+
+fn pathfinding_benchmark(c: &mut Criterion) {
+    let map = generate_map();
+    let blockers = HashSet::default();
+    let (start, goal) = far_apart_tiles(&map);
+
+    for (name, options) in [
+        ("straight", PathOptions::default()),
+        (
+            "diagonal",
+            PathOptions {
+                diagonal: true,
+                ..PathOptions::default()
+            },
+        ),
+    ]
+    .iter()
+    {
+        let grid = PathGrid::new(&map, &blockers, *options);
+        c.bench_function(&format!("a_star_{}", name), |b| {
+            b.iter(|| grid.find_path(black_box(start), black_box(goal)))
+        });
+        c.bench_function(&format!("dijkstra_map_{}", name), |b| {
+            b.iter(|| DijkstraMap::new(&grid, black_box(&[goal])))
+        });
+    }
+}
+
+criterion_group!(benches, pathfinding_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,480 @@
+use bevy::{prelude::*, utils::HashSet};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+
+use crate::combat::{GameOver, Health};
+use crate::events::MeleeAttack;
+use crate::monster::{Monster, OccupiedTiles};
+use crate::player::{Player, PlayerSystem};
+use crate::plugins::map::coordinates::{tile_to_world, world_to_tile};
+use crate::plugins::map::fov::{compute_fov, FOV_RADIUS};
+use crate::plugins::map::map_config::MapConfig;
+use crate::plugins::map::map_creator::Map;
+use crate::plugins::map::pathfinding::{DijkstraMap, PathGrid, PathOptions, Terrain};
+use crate::state::AppState;
+use crate::turn::{TakesTurn, TurnQueue, TurnSystem, ACTION_COST};
+use crate::world_seed::{WorldSeed, AI_STREAM};
+
+// Monsters see as far as the player does.
+pub const SIGHT_RADIUS: i32 = FOV_RADIUS;
+// Share of its health under which a monster flees from the player.
+const FLEE_HEALTH: f32 = 0.25;
+// Chance for an idle monster to start wandering on its turn.
+const WANDER_CHANCE: f64 = 0.2;
+// Wandering monsters head to a tile at most this many tiles away.
+const WANDER_RADIUS: i32 = 6;
+// Upper bound of monster turns played in a frame, in case no player is left.
+const MAX_MONSTER_TURNS_PER_FRAME: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AiState {
+    // Waits on its tile.
+    Idle,
+    // Walks to a random tile nearby.
+    Wander { target: (i32, i32) },
+    // Walks to the player, or to where it last saw the player.
+    Chase { last_seen: (i32, i32) },
+    // Walks away from the player.
+    Flee,
+}
+
+pub struct Ai {
+    pub state: AiState,
+}
+
+impl Default for Ai {
+    fn default() -> Self {
+        Ai { state: AiState::Idle }
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AiSystem {
+    Turns,
+}
+
+// Random draws of the monsters, from their own stream of the world seed.
+pub struct AiRng(pub ChaCha8Rng);
+
+impl FromWorld for AiRng {
+    fn from_world(world: &mut World) -> Self {
+        let seed = *world.get_resource_or_insert_with(WorldSeed::default);
+        AiRng(seed.rng(AI_STREAM))
+    }
+}
+
+//
+// What a monster knows when it takes a decision.
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Perception {
+    // Tile of the player when the monster sees it.
+    pub player: Option<(i32, i32)>,
+    // Current health over maximum health.
+    pub health: f32,
+}
+
+//
+// Sight is taken as symmetric: a monster sees the player when the player's
+// field of view holds the monster's tile, so a single field of view serves
+// every monster.
+//
+pub fn can_see(player_view: &HashSet<(i32, i32)>, monster: (i32, i32), player: (i32, i32)) -> bool {
+    in_sight_range(monster, player) && player_view.contains(&monster)
+}
+
+fn in_sight_range(from: (i32, i32), to: (i32, i32)) -> bool {
+    (from.0 - to.0).abs().max((from.1 - to.1).abs()) <= SIGHT_RADIUS
+}
+
+//
+// Distances to the player walked uphill by the fleeing monsters, one map per
+// terrain. They don't depend on where the monsters are, so they are kept
+// until the player moves or the level changes.
+//
+#[derive(Default)]
+pub struct FleeMaps {
+    player: Option<(i32, i32)>,
+    maps: HashMap<Terrain, DijkstraMap>,
+}
+
+impl FleeMaps {
+    pub fn get(&mut self, map: &Map, player: (i32, i32), terrain: Terrain) -> &DijkstraMap {
+        if self.player != Some(player) {
+            self.clear();
+            self.player = Some(player);
+        }
+
+        self.maps.entry(terrain).or_insert_with(|| {
+            let blockers = HashSet::default();
+            let options = PathOptions {
+                diagonal: true,
+                terrain,
+            };
+            DijkstraMap::new(&PathGrid::new(map, &blockers, options), &[player])
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.player = None;
+        self.maps.clear();
+    }
+}
+
+fn wander_target<R: Rng>(grid: &PathGrid, from: (i32, i32), rng: &mut R) -> Option<(i32, i32)> {
+    for _ in 0..8 {
+        let target = (
+            from.0 + rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS),
+            from.1 + rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS),
+        );
+        if target != from && grid.is_passable(target) {
+            return Some(target);
+        }
+    }
+    None
+}
+
+//
+// Transitions of the state machine. Seeing the player wins over everything:
+// the monster flees when hurt, chases otherwise. Without the player in sight
+// it goes on with what it was doing until it's done, then idles. Wandering
+// targets are tiles of the grid the monster can stand on.
+//
+pub fn next_state<R: Rng>(
+    state: AiState,
+    position: (i32, i32),
+    perception: &Perception,
+    grid: &PathGrid,
+    rng: &mut R,
+) -> AiState {
+    if let Some(player) = perception.player {
+        if perception.health <= FLEE_HEALTH {
+            return AiState::Flee;
+        }
+        return AiState::Chase { last_seen: player };
+    }
+
+    match state {
+        AiState::Chase { last_seen } if last_seen != position => state,
+        AiState::Wander { target } if target != position => state,
+        AiState::Idle if rng.gen_bool(WANDER_CHANCE) => match wander_target(grid, position, rng) {
+            Some(target) => AiState::Wander { target },
+            None => AiState::Idle,
+        },
+        _ => AiState::Idle,
+    }
+}
+
+//
+// Tile the monster wants to move to in its current state, None to wait. Paths
+// go around the blockers of the grid but may end on an occupied target. A
+// fleeing monster walks uphill on the distances to the player.
+//
+pub fn next_step(
+    state: AiState,
+    position: (i32, i32),
+    flee_map: Option<&DijkstraMap>,
+    grid: &PathGrid,
+) -> Option<(i32, i32)> {
+    match state {
+        AiState::Idle => None,
+        AiState::Wander { target } | AiState::Chase { last_seen: target } => grid
+            .find_path(position, target)
+            .and_then(|path| path.first().copied()),
+        AiState::Flee => flee_map.and_then(|flee_map| flee_map.uphill(grid, position)),
+    }
+}
+
+//
+// Plays the turns of the monsters until it is the player's turn again, so the
+// player doesn't wait a frame per monster.
+//
+#[allow(clippy::too_many_arguments)]
+pub fn ai_turn_system(
+    config: Res<MapConfig>,
+    map: Res<Map>,
+    mut rng: ResMut<AiRng>,
+    mut turn_queue: ResMut<TurnQueue>,
+    mut occupied: ResMut<OccupiedTiles>,
+    mut flee_maps: ResMut<FleeMaps>,
+    mut attacks: EventWriter<MeleeAttack>,
+    game_over: Option<Res<GameOver>>,
+    mut actors: Query<(Entity, &mut TakesTurn)>,
+    mut monsters: Query<(&Monster, &mut Transform, &mut Ai, &Health), Without<Player>>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+) {
+    if game_over.is_some() {
+        return;
+    }
+
+    let (player_entity, player) = match player_query.iter().next() {
+        Some((entity, transform)) => (Some(entity), Some(world_to_tile(&config, &transform.translation))),
+        None => (None, None),
+    };
+
+    // The flee maps are dropped whenever the Map resource is mutated: by the
+    // field of view exploring tiles as the player moves, which moves the
+    // flee maps' target anyway, by new tile definitions or by a new map.
+    if map.is_changed() {
+        flee_maps.clear();
+    }
+    // Computed once the first monster close enough to the player plays.
+    let mut player_view: Option<HashSet<(i32, i32)>> = None;
+
+    for _ in 0..MAX_MONSTER_TURNS_PER_FRAME {
+        let current = match turn_queue.current {
+            Some(current) => current,
+            None => return,
+        };
+
+        if let Ok((monster, mut transform, mut ai, health)) = monsters.get_mut(current) {
+            let position = world_to_tile(&config, &transform.translation);
+            let perception = Perception {
+                player: player.filter(|player| {
+                    in_sight_range(position, *player)
+                        && can_see(
+                            player_view.get_or_insert_with(|| compute_fov(&map, *player, SIGHT_RADIUS)),
+                            position,
+                            *player,
+                        )
+                }),
+                health: health.fraction(),
+            };
+
+            let mut blockers: HashSet<(i32, i32)> = occupied.0.clone();
+            blockers.remove(&position);
+            if let Some(player) = player {
+                blockers.insert(player);
+            }
+            let options = PathOptions {
+                diagonal: true,
+                terrain: monster.terrain,
+            };
+            let grid = PathGrid::new(&map, &blockers, options);
+            ai.state = next_state(ai.state, position, &perception, &grid, &mut rng.0);
+
+            let flee_map = match (ai.state, player) {
+                (AiState::Flee, Some(player)) => Some(flee_maps.get(&map, player, monster.terrain)),
+                _ => None,
+            };
+
+            match next_step(ai.state, position, flee_map, &grid) {
+                // Chasing monsters attack the player instead of stepping on it.
+                Some(step) if Some(step) == player && matches!(ai.state, AiState::Chase { .. }) => {
+                    if let Some(player_entity) = player_entity {
+                        attacks.send(MeleeAttack {
+                            attacker: current,
+                            target: player_entity,
+                        });
+                    }
+                }
+                // Monsters never step on the player or on each other.
+                Some(step) if !blockers.contains(&step) => {
+                    occupied.0.remove(&position);
+                    occupied.0.insert(step);
+                    transform.translation = tile_to_world(&config, step, transform.translation.z);
+                }
+                // Give up on a target that can't be reached anymore.
+                _ if perception.player.is_none() => ai.state = AiState::Idle,
+                _ => {}
+            }
+        } else {
+            return;
+        }
+
+        if let Ok((_, mut takes_turn)) = actors.get_mut(current) {
+            turn_queue.end_turn(&mut takes_turn, ACTION_COST);
+        }
+
+        let mut scheduled: Vec<(Entity, Mut<TakesTurn>)> = actors.iter_mut().collect();
+        turn_queue.schedule(&mut scheduled);
+    }
+}
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<AiRng>()
+            .init_resource::<FleeMaps>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(
+                        ai_turn_system
+                            .system()
+                            .label(AiSystem::Turns)
+                            .after(TurnSystem::Schedule)
+                            .after(PlayerSystem::Movement),
+                    ),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Direction;
+    use crate::turn::{turn_scheduler_system, NORMAL_SPEED};
+    use bevy::app::Events;
+
+    fn app(rows: &[&str]) -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(MapConfig::default())
+            .insert_resource(Map::from_rows(rows))
+            .insert_resource(WorldSeed(3))
+            .init_resource::<AiRng>()
+            .init_resource::<FleeMaps>()
+            .init_resource::<TurnQueue>()
+            .init_resource::<OccupiedTiles>()
+            .add_event::<MeleeAttack>()
+            .add_system(turn_scheduler_system.system().label(TurnSystem::Schedule))
+            .add_system(ai_turn_system.system().after(TurnSystem::Schedule));
+        app
+    }
+
+    fn transform(app: &AppBuilder, tile: (i32, i32)) -> Transform {
+        let config = app.app.world.get_resource::<MapConfig>().unwrap();
+        Transform::from_translation(tile_to_world(config, tile, 0.0))
+    }
+
+    // Spawned first, the monster plays before the player on equal energy.
+    fn spawn_monster(app: &mut AppBuilder, tile: (i32, i32)) -> Entity {
+        let transform = transform(app, tile);
+        app.app.world.get_resource_mut::<OccupiedTiles>().unwrap().0.insert(tile);
+        app.world_mut()
+            .spawn()
+            .insert(Monster {
+                kind: "wolf".to_string(),
+                name: "wolf".to_string(),
+                terrain: Terrain::Land,
+            })
+            .insert(transform)
+            .insert(Ai::default())
+            .insert(Health::new(12))
+            .insert(TakesTurn::new(NORMAL_SPEED))
+            .id()
+    }
+
+    fn spawn_player(app: &mut AppBuilder, tile: (i32, i32)) -> Entity {
+        let transform = transform(app, tile);
+        app.world_mut()
+            .spawn()
+            .insert(Player {
+                speed: 32.0,
+                direction: Direction::Idle,
+            })
+            .insert(transform)
+            .insert(TakesTurn::new(NORMAL_SPEED))
+            .id()
+    }
+
+    fn move_to(app: &mut AppBuilder, entity: Entity, tile: (i32, i32)) {
+        if app.app.world.get::<Monster>(entity).is_some() {
+            let from = tile_of(app, entity);
+            let mut occupied = app.app.world.get_resource_mut::<OccupiedTiles>().unwrap();
+            occupied.0.remove(&from);
+            occupied.0.insert(tile);
+        }
+        let transform = transform(app, tile);
+        *app.app.world.get_mut::<Transform>(entity).unwrap() = transform;
+    }
+
+    fn tile_of(app: &AppBuilder, entity: Entity) -> (i32, i32) {
+        let config = app.app.world.get_resource::<MapConfig>().unwrap();
+        world_to_tile(config, &app.app.world.get::<Transform>(entity).unwrap().translation)
+    }
+
+    fn state_of(app: &AppBuilder, monster: Entity) -> AiState {
+        app.app.world.get::<Ai>(monster).unwrap().state
+    }
+
+    // Lets the monsters play until the player's turn, then passes it.
+    fn play_turn(app: &mut AppBuilder, player: Entity) {
+        app.app.update();
+        assert!(app.app.world.get_resource::<TurnQueue>().unwrap().is_turn_of(player));
+
+        let mut takes_turn = *app.app.world.get::<TakesTurn>(player).unwrap();
+        app.app
+            .world
+            .get_resource_mut::<TurnQueue>()
+            .unwrap()
+            .end_turn(&mut takes_turn, ACTION_COST);
+        *app.app.world.get_mut::<TakesTurn>(player).unwrap() = takes_turn;
+    }
+
+    fn distance(a: (i32, i32), b: (i32, i32)) -> i32 {
+        (a.0 - b.0).abs().max((a.1 - b.1).abs())
+    }
+
+    #[test]
+    fn monsters_go_from_idle_to_chase_to_flee() {
+        let mut app = app(&["....#...", "....#...", "....#..."]);
+        let monster = spawn_monster(&mut app, (0, 1));
+        let player = spawn_player(&mut app, (7, 1));
+
+        // Behind the wall, the player isn't seen.
+        play_turn(&mut app, player);
+        assert!(!matches!(state_of(&app, monster), AiState::Chase { .. } | AiState::Flee));
+
+        // In sight, the monster closes in.
+        move_to(&mut app, monster, (0, 1));
+        move_to(&mut app, player, (3, 1));
+        play_turn(&mut app, player);
+        assert_eq!(state_of(&app, monster), AiState::Chase { last_seen: (3, 1) });
+        assert_eq!(distance(tile_of(&app, monster), (3, 1)), 2);
+
+        // Badly hurt, it runs away.
+        app.app.world.get_mut::<Health>(monster).unwrap().current = 1;
+        play_turn(&mut app, player);
+        assert_eq!(state_of(&app, monster), AiState::Flee);
+        assert_eq!(distance(tile_of(&app, monster), (3, 1)), 3);
+    }
+
+    #[test]
+    fn chasing_monsters_attack_instead_of_stepping_on_the_player() {
+        let mut app = app(&["....", "....", "...."]);
+        let monster = spawn_monster(&mut app, (1, 1));
+        let player = spawn_player(&mut app, (2, 1));
+
+        play_turn(&mut app, player);
+        assert_eq!(tile_of(&app, monster), (1, 1));
+
+        let events = app.app.world.get_resource::<Events<MeleeAttack>>().unwrap();
+        let mut reader = events.get_reader();
+        let attacks: Vec<&MeleeAttack> = reader.iter(events).collect();
+        assert_eq!(attacks.len(), 1);
+        assert_eq!((attacks[0].attacker, attacks[0].target), (monster, player));
+    }
+
+    #[test]
+    fn lost_players_are_searched_where_last_seen() {
+        let mut app = app(&["....#...", "....#...", "....#..."]);
+        let monster = spawn_monster(&mut app, (1, 1));
+        let player = spawn_player(&mut app, (3, 1));
+
+        play_turn(&mut app, player);
+        assert_eq!(state_of(&app, monster), AiState::Chase { last_seen: (3, 1) });
+
+        // The player hid behind the wall.
+        move_to(&mut app, player, (7, 2));
+        play_turn(&mut app, player);
+        assert_eq!(state_of(&app, monster), AiState::Chase { last_seen: (3, 1) });
+        assert_eq!(tile_of(&app, monster), (3, 1));
+    }
+
+    #[test]
+    fn flee_maps_are_kept_until_the_player_moves() {
+        let map = Map::from_rows(&["....", "...."]);
+        let mut flee_maps = FleeMaps::default();
+
+        assert_eq!(flee_maps.get(&map, (0, 0), Terrain::Land).get((3, 0)), Some(30));
+        assert_eq!(flee_maps.maps.len(), 1);
+        flee_maps.get(&map, (0, 0), Terrain::Water);
+        assert_eq!(flee_maps.maps.len(), 2);
+
+        assert_eq!(flee_maps.get(&map, (3, 0), Terrain::Land).get((3, 0)), Some(0));
+        assert_eq!(flee_maps.maps.len(), 1);
+    }
+}
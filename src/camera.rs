@@ -0,0 +1,277 @@
+use bevy::prelude::*;
+
+use crate::events::GameEvent;
+use crate::input::{Action, ActionState, InputSystem};
+use crate::player::{Direction, Player};
+use crate::plugins::map::map_config::MapConfig;
+use crate::state::{world_exists, AppState};
+
+// Marker of the camera rendering the map.
+pub struct MainCamera;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CameraSystem {
+    EdgeScrollDetection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    // The camera smoothly keeps the player centered.
+    Follow,
+    // The camera moves a screen at a time when the player crosses its edge.
+    Paging,
+}
+
+pub struct CameraController {
+    pub mode: CameraMode,
+    // Fraction of the distance to the player covered per second in Follow mode.
+    pub follow_speed: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        CameraController {
+            mode: CameraMode::Paging,
+            follow_speed: 5.0,
+        }
+    }
+}
+
+//
+// Keeps the camera center so the view stays inside the map. When the map is
+// smaller than the window, the map is centered instead.
+//
+pub fn clamp_to_map(center: Vec2, view_size: Vec2, config: &MapConfig) -> Vec2 {
+    let map_size = Vec2::new(config.world_width(), config.world_height());
+    let clamp_axis = |center: f32, view: f32, map: f32| {
+        if view >= map {
+            map / 2.0
+        } else {
+            center.max(view / 2.0).min(map - view / 2.0)
+        }
+    };
+
+    Vec2::new(
+        clamp_axis(center.x, view_size.x, map_size.x),
+        clamp_axis(center.y, view_size.y, map_size.y),
+    )
+}
+
+// Directions in which the position is past the edge of the view.
+pub fn edges_crossed(position: Vec2, center: Vec2, view_size: Vec2) -> Vec<Direction> {
+    let mut directions = Vec::new();
+    let half_view = view_size / 2.0;
+
+    if position.x < center.x - half_view.x {
+        directions.push(Direction::Left);
+    } else if position.x >= center.x + half_view.x {
+        directions.push(Direction::Right);
+    }
+    if position.y < center.y - half_view.y {
+        directions.push(Direction::Down);
+    } else if position.y >= center.y + half_view.y {
+        directions.push(Direction::Up);
+    }
+
+    directions
+}
+
+pub fn view_size(windows: &Windows) -> Option<Vec2> {
+    windows
+        .get_primary()
+        .map(|window| Vec2::new(window.width(), window.height()))
+}
+
+pub fn camera_mode_input_system(
+    action_state: Res<ActionState>,
+    mut controller: ResMut<CameraController>,
+) {
+    if action_state.just_pressed(Action::ToggleCameraMode) {
+        controller.mode = match controller.mode {
+            CameraMode::Follow => CameraMode::Paging,
+            CameraMode::Paging => CameraMode::Follow,
+        };
+        info!("Camera mode is now {:?}.", controller.mode);
+    }
+}
+
+//
+// Sends a PlayerScreenEdgeScroll event when the player leaves the view.
+//
+pub fn edge_scroll_detection_system(
+    controller: Res<CameraController>,
+    windows: Res<Windows>,
+    mut events: EventWriter<GameEvent>,
+    player_query: Query<&Transform, (With<Player>, Changed<Transform>)>,
+    camera_query: Query<&Transform, (With<MainCamera>, Without<Player>)>,
+) {
+    if controller.mode != CameraMode::Paging {
+        return;
+    }
+
+    let view_size = match view_size(&windows) {
+        Some(view_size) => view_size,
+        None => return,
+    };
+
+    for player_transform in player_query.iter() {
+        for camera_transform in camera_query.iter() {
+            let crossed = edges_crossed(
+                player_transform.translation.truncate(),
+                camera_transform.translation.truncate(),
+                view_size,
+            );
+            for direction in crossed {
+                events.send(GameEvent::PlayerScreenEdgeScroll(direction));
+            }
+        }
+    }
+}
+
+//
+// Moves the camera a screen at a time on PlayerScreenEdgeScroll events.
+//
+pub fn camera_paging_system(
+    config: Res<MapConfig>,
+    windows: Res<Windows>,
+    mut events: EventReader<GameEvent>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+) {
+    let view_size = match view_size(&windows) {
+        Some(view_size) => view_size,
+        None => return,
+    };
+
+    let mut offset = Vec2::ZERO;
+    for event in events.iter() {
+        match event {
+            GameEvent::PlayerScreenEdgeScroll(direction) => {
+                let (dx, dy) = direction.offset();
+                offset.x += dx as f32 * view_size.x;
+                offset.y += dy as f32 * view_size.y;
+            }
+        }
+    }
+
+    for mut transform in camera_query.iter_mut() {
+        let center = clamp_to_map(transform.translation.truncate() + offset, view_size, &config);
+        transform.translation.x = center.x;
+        transform.translation.y = center.y;
+    }
+}
+
+//
+// Smoothly moves the camera toward the player in Follow mode.
+//
+pub fn camera_follow_system(
+    time: Res<Time>,
+    config: Res<MapConfig>,
+    controller: Res<CameraController>,
+    windows: Res<Windows>,
+    player_query: Query<&Transform, With<Player>>,
+    mut camera_query: Query<&mut Transform, (With<MainCamera>, Without<Player>)>,
+) {
+    if controller.mode != CameraMode::Follow {
+        return;
+    }
+
+    let view_size = match view_size(&windows) {
+        Some(view_size) => view_size,
+        None => return,
+    };
+
+    for player_transform in player_query.iter() {
+        for mut camera_transform in camera_query.iter_mut() {
+            let current = camera_transform.translation.truncate();
+            let target = player_transform.translation.truncate();
+            let step = (controller.follow_speed * time.delta_seconds()).min(1.0);
+            let center = clamp_to_map(current + (target - current) * step, view_size, &config);
+            camera_transform.translation.x = center.x;
+            camera_transform.translation.y = center.y;
+        }
+    }
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CameraController>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(camera_mode_input_system.system().after(InputSystem::Update)),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_exists.system())
+                    .with_system(edge_scroll_detection_system.system().label(CameraSystem::EdgeScrollDetection))
+                    .with_system(camera_paging_system.system().after(CameraSystem::EdgeScrollDetection))
+                    .with_system(camera_follow_system.system()),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 128x96 pixels map.
+    fn config() -> MapConfig {
+        MapConfig {
+            width: 4,
+            height: 3,
+            tile_size: 32,
+            chunk_width: 2,
+            chunk_height: 2,
+        }
+    }
+
+    #[test]
+    fn clamp_to_map_keeps_the_view_inside_the_map() {
+        let config = config();
+        let view_size = Vec2::new(64.0, 64.0);
+        assert_eq!(clamp_to_map(Vec2::new(60.0, 40.0), view_size, &config), Vec2::new(60.0, 40.0));
+        assert_eq!(clamp_to_map(Vec2::new(0.0, -10.0), view_size, &config), Vec2::new(32.0, 32.0));
+        assert_eq!(clamp_to_map(Vec2::new(500.0, 500.0), view_size, &config), Vec2::new(96.0, 64.0));
+    }
+
+    #[test]
+    fn clamp_to_map_centers_maps_smaller_than_the_view() {
+        let config = config();
+        let view_size = Vec2::new(800.0, 600.0);
+        for center in [Vec2::ZERO, Vec2::new(-50.0, 300.0), Vec2::new(1000.0, 1000.0)].iter() {
+            assert_eq!(clamp_to_map(*center, view_size, &config), Vec2::new(64.0, 48.0));
+        }
+
+        // Each axis is handled on its own.
+        let view_size = Vec2::new(200.0, 64.0);
+        assert_eq!(clamp_to_map(Vec2::new(0.0, 90.0), view_size, &config), Vec2::new(64.0, 64.0));
+    }
+
+    #[test]
+    fn the_last_row_and_column_are_inside_the_view() {
+        // View showing the whole map.
+        let center = Vec2::new(64.0, 48.0);
+        let view_size = Vec2::new(128.0, 96.0);
+        assert!(edges_crossed(Vec2::new(0.0, 0.0), center, view_size).is_empty());
+        assert!(edges_crossed(Vec2::new(127.9, 95.9), center, view_size).is_empty());
+        assert_eq!(edges_crossed(Vec2::new(128.0, 50.0), center, view_size), vec![Direction::Right]);
+        assert_eq!(edges_crossed(Vec2::new(10.0, 96.0), center, view_size), vec![Direction::Up]);
+    }
+
+    #[test]
+    fn edges_crossed_reports_each_axis() {
+        let center = Vec2::new(64.0, 48.0);
+        let view_size = Vec2::new(128.0, 96.0);
+        assert_eq!(edges_crossed(Vec2::new(-0.1, 50.0), center, view_size), vec![Direction::Left]);
+        assert_eq!(edges_crossed(Vec2::new(50.0, -0.1), center, view_size), vec![Direction::Down]);
+        assert_eq!(
+            edges_crossed(Vec2::new(130.0, -1.0), center, view_size),
+            vec![Direction::Right, Direction::Down]
+        );
+        assert_eq!(
+            edges_crossed(Vec2::new(-1.0, 100.0), center, view_size),
+            vec![Direction::Left, Direction::Up]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::events::{DamageDealt, Died, MeleeAttack};
+use crate::monster::{Monster, OccupiedTiles};
+use crate::ai::AiSystem;
+use crate::player::{Player, PlayerSystem};
+use crate::plugins::map::coordinates::world_to_tile;
+use crate::plugins::map::map_config::MapConfig;
+use crate::state::AppState;
+use crate::turn::{TakesTurn, TurnQueue};
+use crate::world_seed::{WorldSeed, COMBAT_STREAM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+}
+
+impl Health {
+    pub fn new(max: i32) -> Health {
+        Health { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0
+    }
+
+    // Current health over maximum health.
+    pub fn fraction(&self) -> f32 {
+        self.current as f32 / self.max.max(1) as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Attack(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Defense(pub i32);
+
+// Set when the player dies, with what killed the player.
+pub struct GameOver {
+    pub cause: String,
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CombatSystem {
+    Attack,
+}
+
+// Damage rolls, from their own stream of the world seed.
+pub struct CombatRng(pub ChaCha8Rng);
+
+impl FromWorld for CombatRng {
+    fn from_world(world: &mut World) -> Self {
+        let seed = *world.get_resource_or_insert_with(WorldSeed::default);
+        CombatRng(seed.rng(COMBAT_STREAM))
+    }
+}
+
+//
+// A hit deals between half and all of the attack, minus the defense of the
+// target, and never less than zero.
+//
+pub fn roll_damage<R: Rng>(attack: i32, defense: i32, rng: &mut R) -> i32 {
+    let attack = attack.max(0);
+    let roll = rng.gen_range((attack + 1) / 2..=attack);
+    (roll - defense).max(0)
+}
+
+fn name_of(monster: Option<&Monster>) -> String {
+    match monster {
+        Some(monster) => format!("the {}", monster.name),
+        None => "you".to_string(),
+    }
+}
+
+pub fn attack_system(
+    mut rng: ResMut<CombatRng>,
+    mut attacks: EventReader<MeleeAttack>,
+    mut damage_events: EventWriter<DamageDealt>,
+    mut died_events: EventWriter<Died>,
+    attackers: Query<(&Attack, Option<&Monster>)>,
+    mut targets: Query<(&mut Health, Option<&Defense>, Option<&Monster>)>,
+) {
+    for attack in attacks.iter() {
+        let (attack_value, attacker_name) = match attackers.get(attack.attacker) {
+            Ok((attack_value, monster)) => (attack_value.0, name_of(monster)),
+            Err(_) => continue,
+        };
+        let (mut health, defense, target_monster) = match targets.get_mut(attack.target) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        // Already dead, the corpse is removed at the end of the frame.
+        if health.is_dead() {
+            continue;
+        }
+
+        let defense = defense.map_or(0, |defense| defense.0);
+        let damage = roll_damage(attack_value, defense, &mut rng.0);
+        health.current -= damage;
+
+        let target_name = name_of(target_monster);
+        info!("Hit on {} by {}: {} damage.", target_name, attacker_name, damage);
+        damage_events.send(DamageDealt {
+            attacker: attack.attacker,
+            target: attack.target,
+            damage,
+        });
+
+        if health.is_dead() {
+            died_events.send(Died {
+                entity: attack.target,
+                name: target_name,
+                cause: format!("killed by {}", attacker_name),
+            });
+        }
+    }
+}
+
+//
+// Removes the dead monsters, their tiles and their turns. The player's death
+// ends the run: the player stops taking turns and GameOver is set.
+//
+pub fn death_system(
+    mut commands: Commands,
+    config: Res<MapConfig>,
+    mut occupied: ResMut<OccupiedTiles>,
+    mut turn_queue: ResMut<TurnQueue>,
+    mut died_events: EventReader<Died>,
+    players: Query<&Player>,
+    monsters: Query<&Transform, With<Monster>>,
+) {
+    for died in died_events.iter() {
+        turn_queue.remove(died.entity);
+        if players.get(died.entity).is_ok() {
+            info!("You die, {}.", died.cause);
+            commands.entity(died.entity).remove::<TakesTurn>();
+            commands.insert_resource(GameOver {
+                cause: died.cause.clone(),
+            });
+        } else {
+            info!("Death of {}.", died.name);
+            if let Ok(transform) = monsters.get(died.entity) {
+                occupied.0.remove(&world_to_tile(&config, &transform.translation));
+            }
+            commands.entity(died.entity).despawn();
+        }
+    }
+}
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CombatRng>()
+            .add_event::<MeleeAttack>()
+            .add_event::<DamageDealt>()
+            .add_event::<Died>()
+            // The attacks of a frame are resolved once everybody acted, the
+            // dead are then gone before the next frame's turns.
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(
+                        attack_system
+                            .system()
+                            .label(CombatSystem::Attack)
+                            .after(PlayerSystem::Movement)
+                            .after(AiSystem::Turns),
+                    )
+                    .with_system(death_system.system().after(CombatSystem::Attack)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::map::coordinates::tile_to_world;
+    use crate::plugins::map::pathfinding::Terrain;
+    use crate::turn::NORMAL_SPEED;
+    use bevy::app::Events;
+
+    #[test]
+    fn damage_rolls_come_from_the_seed() {
+        let mut rng = WorldSeed(0).rng(COMBAT_STREAM);
+        let rolls: Vec<i32> = (0..8).map(|_| roll_damage(10, 2, &mut rng)).collect();
+        assert_eq!(rolls, vec![4, 3, 8, 3, 4, 4, 5, 5]);
+    }
+
+    #[test]
+    fn damage_is_never_negative() {
+        let mut rng = WorldSeed(0).rng(COMBAT_STREAM);
+        for _ in 0..100 {
+            let damage = roll_damage(6, 1, &mut rng);
+            assert!((2..=5).contains(&damage), "{}", damage);
+            assert_eq!(roll_damage(3, 10, &mut rng), 0);
+            assert_eq!(roll_damage(-4, 0, &mut rng), 0);
+        }
+    }
+
+    #[test]
+    fn dead_monsters_leave_their_tile_and_the_turns() {
+        let config = MapConfig::default();
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(config)
+            .init_resource::<OccupiedTiles>()
+            .init_resource::<TurnQueue>()
+            .add_event::<Died>()
+            .add_system(death_system.system());
+
+        let tile = (2, 1);
+        let monster = app
+            .world_mut()
+            .spawn()
+            .insert(Monster {
+                kind: "wolf".to_string(),
+                name: "wolf".to_string(),
+                terrain: Terrain::Land,
+            })
+            .insert(Transform::from_translation(tile_to_world(&config, tile, 4.0)))
+            .insert(TakesTurn::new(NORMAL_SPEED))
+            .id();
+        app.world_mut().get_resource_mut::<OccupiedTiles>().unwrap().0.insert(tile);
+        app.world_mut().get_resource_mut::<TurnQueue>().unwrap().current = Some(monster);
+
+        app.world_mut().get_resource_mut::<Events<Died>>().unwrap().send(Died {
+            entity: monster,
+            name: "the wolf".to_string(),
+            cause: "killed by you".to_string(),
+        });
+        app.app.update();
+
+        assert!(app.app.world.get_entity(monster).is_none());
+        assert!(app.app.world.get_resource::<OccupiedTiles>().unwrap().0.is_empty());
+        assert_eq!(app.app.world.get_resource::<TurnQueue>().unwrap().current, None);
+        assert!(app.app.world.get_resource::<GameOver>().is_none());
+    }
+}
@@ -1,4 +1,36 @@
+use bevy::prelude::Entity;
+
+use crate::player::Direction;
+use crate::plugins::map::map_creator::TileInfo;
+
 pub enum GameEvent {
-    //Fired when the player moves past the screen edge.
-    PlayerScreenEdgeScroll
-}
\ No newline at end of file
+    //Fired when the player moves past the screen edge, the camera pages in
+    //this direction.
+    PlayerScreenEdgeScroll(Direction)
+}
+
+//Fired when the player clicks on a map tile.
+pub struct TileInspected {
+    pub tile: (i32, i32),
+    pub tile_info: TileInfo,
+}
+
+//Fired when an entity bumps into another one on its turn.
+pub struct MeleeAttack {
+    pub attacker: Entity,
+    pub target: Entity,
+}
+
+//Fired for every hit, even the ones fully absorbed by the defense.
+pub struct DamageDealt {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub damage: i32,
+}
+
+//Fired once when an entity's health drops to zero.
+pub struct Died {
+    pub entity: Entity,
+    pub name: String,
+    pub cause: String,
+}
@@ -0,0 +1,638 @@
+use bevy::{prelude::*, utils::HashSet};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_4;
+use std::fs;
+
+use crate::player::Direction;
+
+pub const INPUT_CONFIG_PATH: &str = "config/input.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveUpLeft,
+    MoveUpRight,
+    MoveDownLeft,
+    MoveDownRight,
+    ToggleFullscreen,
+    ToggleCameraMode,
+    Pause,
+    Inventory,
+    Confirm,
+    Quit,
+}
+
+impl Action {
+    pub const MOVES: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveUpLeft,
+        Action::MoveUpRight,
+        Action::MoveDownLeft,
+        Action::MoveDownRight,
+    ];
+
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            Action::MoveLeft => Some(Direction::Left),
+            Action::MoveRight => Some(Direction::Right),
+            Action::MoveUp => Some(Direction::Up),
+            Action::MoveDown => Some(Direction::Down),
+            Action::MoveUpLeft => Some(Direction::UpLeft),
+            Action::MoveUpRight => Some(Direction::UpRight),
+            Action::MoveDownLeft => Some(Direction::DownLeft),
+            Action::MoveDownRight => Some(Direction::DownRight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum InputPreset {
+    Arrows,
+    Vi,
+    Wasd,
+    Numpad,
+}
+
+impl InputPreset {
+    fn movement_keys(&self) -> [(Action, KeyCode); 8] {
+        match self {
+            InputPreset::Arrows => [
+                (Action::MoveLeft, KeyCode::Left),
+                (Action::MoveRight, KeyCode::Right),
+                (Action::MoveUp, KeyCode::Up),
+                (Action::MoveDown, KeyCode::Down),
+                (Action::MoveUpLeft, KeyCode::Home),
+                (Action::MoveUpRight, KeyCode::PageUp),
+                (Action::MoveDownLeft, KeyCode::End),
+                (Action::MoveDownRight, KeyCode::PageDown),
+            ],
+            InputPreset::Vi => [
+                (Action::MoveLeft, KeyCode::H),
+                (Action::MoveRight, KeyCode::L),
+                (Action::MoveUp, KeyCode::K),
+                (Action::MoveDown, KeyCode::J),
+                (Action::MoveUpLeft, KeyCode::Y),
+                (Action::MoveUpRight, KeyCode::U),
+                (Action::MoveDownLeft, KeyCode::B),
+                (Action::MoveDownRight, KeyCode::N),
+            ],
+            InputPreset::Wasd => [
+                (Action::MoveLeft, KeyCode::A),
+                (Action::MoveRight, KeyCode::D),
+                (Action::MoveUp, KeyCode::W),
+                (Action::MoveDown, KeyCode::S),
+                (Action::MoveUpLeft, KeyCode::Q),
+                (Action::MoveUpRight, KeyCode::E),
+                (Action::MoveDownLeft, KeyCode::Z),
+                (Action::MoveDownRight, KeyCode::C),
+            ],
+            InputPreset::Numpad => [
+                (Action::MoveLeft, KeyCode::Numpad4),
+                (Action::MoveRight, KeyCode::Numpad6),
+                (Action::MoveUp, KeyCode::Numpad8),
+                (Action::MoveDown, KeyCode::Numpad2),
+                (Action::MoveUpLeft, KeyCode::Numpad7),
+                (Action::MoveUpRight, KeyCode::Numpad9),
+                (Action::MoveDownLeft, KeyCode::Numpad1),
+                (Action::MoveDownRight, KeyCode::Numpad3),
+            ],
+        }
+    }
+}
+
+//
+// Gamepad part of config/input.ron. The d-pad buttons, the d-pad axes and the
+// left stick always move the player; the buttons bind the other actions.
+//
+#[derive(Debug, Clone, Deserialize)]
+pub struct GamepadConfig {
+    // Stick deflection, between 0 and 1, under which the stick is ignored.
+    pub deadzone: f32,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        let mut buttons = HashMap::new();
+        buttons.insert(Action::ToggleCameraMode, vec![GamepadButtonType::North]);
+        buttons.insert(Action::ToggleFullscreen, vec![GamepadButtonType::Select]);
+        buttons.insert(Action::Pause, vec![GamepadButtonType::Start]);
+        buttons.insert(Action::Inventory, vec![GamepadButtonType::West]);
+        buttons.insert(Action::Confirm, vec![GamepadButtonType::South]);
+
+        GamepadConfig {
+            deadzone: 0.5,
+            buttons,
+        }
+    }
+}
+
+//
+// Content of config/input.ron. The preset gives the movement keys, the
+// bindings replace the keys of single actions.
+//
+#[derive(Debug, Clone, Deserialize)]
+pub struct InputConfig {
+    pub preset: InputPreset,
+    // Seconds a key is held before it starts repeating.
+    pub repeat_delay: f32,
+    // Seconds between two repeats.
+    pub repeat_interval: f32,
+    #[serde(default)]
+    pub bindings: HashMap<Action, Vec<KeyCode>>,
+    #[serde(default)]
+    pub gamepad: GamepadConfig,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            preset: InputPreset::Arrows,
+            repeat_delay: 0.25,
+            repeat_interval: 0.08,
+            bindings: HashMap::new(),
+            gamepad: GamepadConfig::default(),
+        }
+    }
+}
+
+impl InputConfig {
+    pub fn load(path: &str) -> InputConfig {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                info!("No input configuration at {}, using the arrow keys.", path);
+                return InputConfig::default();
+            }
+        };
+
+        match ron::de::from_str(&content) {
+            Ok(config) => config,
+            Err(error) => {
+                warn!("Invalid input configuration {}: {}", path, error);
+                InputConfig::default()
+            }
+        }
+    }
+}
+
+pub struct InputMap {
+    pub bindings: HashMap<Action, Vec<KeyCode>>,
+    pub gamepad_buttons: HashMap<Action, Vec<GamepadButtonType>>,
+    pub deadzone: f32,
+    pub repeat_delay: f32,
+    pub repeat_interval: f32,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        InputMap::from_config(&InputConfig::load(INPUT_CONFIG_PATH))
+    }
+}
+
+impl InputMap {
+    pub fn from_config(config: &InputConfig) -> InputMap {
+        let mut bindings: HashMap<Action, Vec<KeyCode>> = HashMap::new();
+        for (action, key) in config.preset.movement_keys().iter() {
+            bindings.insert(*action, vec![*key]);
+        }
+        bindings.insert(Action::ToggleFullscreen, vec![KeyCode::F]);
+        bindings.insert(Action::ToggleCameraMode, vec![KeyCode::V]);
+        bindings.insert(Action::Pause, vec![KeyCode::Escape]);
+        bindings.insert(Action::Inventory, vec![KeyCode::I]);
+        bindings.insert(Action::Confirm, vec![KeyCode::Return, KeyCode::NumpadEnter]);
+        bindings.insert(Action::Quit, vec![KeyCode::Q]);
+
+        for (action, keys) in config.bindings.iter() {
+            bindings.insert(*action, keys.clone());
+        }
+
+        let mut gamepad_buttons = config.gamepad.buttons.clone();
+        let dpad = [
+            (Action::MoveLeft, GamepadButtonType::DPadLeft),
+            (Action::MoveRight, GamepadButtonType::DPadRight),
+            (Action::MoveUp, GamepadButtonType::DPadUp),
+            (Action::MoveDown, GamepadButtonType::DPadDown),
+        ];
+        for (action, button) in dpad.iter() {
+            gamepad_buttons.entry(*action).or_insert_with(Vec::new).push(*button);
+        }
+
+        InputMap {
+            bindings,
+            gamepad_buttons,
+            deadzone: config.gamepad.deadzone,
+            repeat_delay: config.repeat_delay,
+            repeat_interval: config.repeat_interval,
+        }
+    }
+}
+
+//
+// State of the actions for the current frame. An action is triggered when it
+// is pressed, then again at a regular interval while it is held.
+//
+#[derive(Default)]
+pub struct ActionState {
+    just_pressed: HashSet<Action>,
+    triggered: HashSet<Action>,
+    // Seconds each held action has been held, and when it repeats next.
+    held: HashMap<Action, (f32, f32)>,
+}
+
+impl ActionState {
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    pub fn triggered(&self, action: Action) -> bool {
+        self.triggered.contains(&action)
+    }
+
+    // Whether one of the action's inputs is held, whatever the repeat timing.
+    pub fn pressed(&self, action: Action) -> bool {
+        self.held.contains_key(&action)
+    }
+
+    //
+    // Like just_pressed, but the press is used up: a menu opened by a key
+    // doesn't see that same key press and close right away.
+    //
+    pub fn consume(&mut self, action: Action) -> bool {
+        self.triggered.remove(&action);
+        self.just_pressed.remove(&action)
+    }
+
+    //
+    // Updates an action from whether one of its inputs is held this frame.
+    // Other input sources feed the same state through this method.
+    //
+    pub fn update(&mut self, action: Action, held: bool, delta_seconds: f32, input_map: &InputMap) {
+        if !held {
+            self.held.remove(&action);
+            return;
+        }
+
+        match self.held.get_mut(&action) {
+            None => {
+                self.held.insert(action, (0.0, input_map.repeat_delay));
+                self.just_pressed.insert(action);
+                self.triggered.insert(action);
+            }
+            Some((held_time, next_repeat)) => {
+                *held_time += delta_seconds;
+                if *held_time >= *next_repeat {
+                    *next_repeat += input_map.repeat_interval.max(f32::EPSILON);
+                    self.triggered.insert(action);
+                }
+            }
+        }
+    }
+
+    pub fn clear_frame(&mut self) {
+        self.just_pressed.clear();
+        self.triggered.clear();
+    }
+}
+
+//
+// Gamepads currently plugged in. Bevy only reports them through GamepadEvent.
+//
+#[derive(Default)]
+pub struct ConnectedGamepads(pub HashSet<Gamepad>);
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InputSystem {
+    Gamepads,
+    Update,
+}
+
+//
+// Move action of a stick, snapped to one of eight directions. Returns None
+// while the stick is in its deadzone.
+//
+pub fn stick_move(x: f32, y: f32, deadzone: f32) -> Option<Action> {
+    if Vec2::new(x, y).length() < deadzone.max(f32::EPSILON) {
+        return None;
+    }
+
+    // Angle in eighths of a turn, counter-clockwise from the right.
+    let octant = ((y.atan2(x) / FRAC_PI_4).round() as i32).rem_euclid(8);
+    let action = match octant {
+        0 => Action::MoveRight,
+        1 => Action::MoveUpRight,
+        2 => Action::MoveUp,
+        3 => Action::MoveUpLeft,
+        4 => Action::MoveLeft,
+        5 => Action::MoveDownLeft,
+        6 => Action::MoveDown,
+        _ => Action::MoveDownRight,
+    };
+
+    Some(action)
+}
+
+pub fn gamepad_connection_system(
+    mut connected_gamepads: ResMut<ConnectedGamepads>,
+    mut events: EventReader<GamepadEvent>,
+) {
+    for GamepadEvent(gamepad, event_type) in events.iter() {
+        match event_type {
+            GamepadEventType::Connected => {
+                info!("Gamepad {:?} connected.", gamepad);
+                connected_gamepads.0.insert(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                info!("Gamepad {:?} disconnected.", gamepad);
+                connected_gamepads.0.remove(gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+//
+// Gathers the held actions of the keyboard and of every gamepad, then updates
+// the action state once per action so both sources share the repeat timing.
+//
+pub fn action_system(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    button_input: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    connected_gamepads: Res<ConnectedGamepads>,
+    input_map: Res<InputMap>,
+    mut action_state: ResMut<ActionState>,
+) {
+    action_state.clear_frame();
+
+    let mut held: HashSet<Action> = HashSet::default();
+    for (action, keys) in input_map.bindings.iter() {
+        if keys.iter().any(|key| keyboard_input.pressed(*key)) {
+            held.insert(*action);
+        }
+    }
+
+    for gamepad in connected_gamepads.0.iter() {
+        for (action, buttons) in input_map.gamepad_buttons.iter() {
+            if buttons
+                .iter()
+                .any(|button| button_input.pressed(GamepadButton(*gamepad, *button)))
+            {
+                held.insert(*action);
+            }
+        }
+
+        let sticks = [
+            (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            (GamepadAxisType::DPadX, GamepadAxisType::DPadY),
+        ];
+        for (axis_x, axis_y) in sticks.iter() {
+            let x = axes.get(GamepadAxis(*gamepad, *axis_x)).unwrap_or(0.0);
+            let y = axes.get(GamepadAxis(*gamepad, *axis_y)).unwrap_or(0.0);
+            if let Some(action) = stick_move(x, y, input_map.deadzone) {
+                held.insert(action);
+            }
+        }
+    }
+
+    // Actions may be bound to the keyboard, to a gamepad or to both, the
+    // sticks always move.
+    let actions: HashSet<Action> = input_map
+        .bindings
+        .keys()
+        .chain(input_map.gamepad_buttons.keys())
+        .chain(Action::MOVES.iter())
+        .copied()
+        .collect();
+    for action in actions.iter() {
+        action_state.update(*action, held.contains(action), time.delta_seconds(), &input_map);
+    }
+}
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<InputMap>()
+            .init_resource::<ActionState>()
+            .init_resource::<ConnectedGamepads>()
+            .add_system(gamepad_connection_system.system().label(InputSystem::Gamepads))
+            .add_system(
+                action_system
+                    .system()
+                    .label(InputSystem::Update)
+                    .after(InputSystem::Gamepads),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::app::Events;
+    use bevy::input::gamepad::GamepadEventRaw;
+
+    fn app(input_map: InputMap) -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(bevy::input::InputPlugin)
+            .insert_resource(input_map)
+            .add_plugin(InputPlugin);
+        app
+    }
+
+    fn default_map() -> InputMap {
+        InputMap::from_config(&InputConfig::default())
+    }
+
+    // Raw events, as gilrs sends them. Bevy filters them with the
+    // GamepadSettings into the button and axis inputs and the GamepadEvents.
+    fn send(app: &mut AppBuilder, gamepad: Gamepad, event_type: GamepadEventType) {
+        app.world_mut()
+            .get_resource_mut::<Events<GamepadEventRaw>>()
+            .unwrap()
+            .send(GamepadEventRaw(gamepad, event_type));
+    }
+
+    fn connect(app: &mut AppBuilder) -> Gamepad {
+        let gamepad = Gamepad(0);
+        send(app, gamepad, GamepadEventType::Connected);
+        app.app.update();
+        gamepad
+    }
+
+    fn press(app: &mut AppBuilder, gamepad: Gamepad, button: GamepadButtonType, value: f32) {
+        send(app, gamepad, GamepadEventType::ButtonChanged(button, value));
+        app.app.update();
+    }
+
+    fn tilt(app: &mut AppBuilder, gamepad: Gamepad, axis: GamepadAxisType, value: f32) {
+        send(app, gamepad, GamepadEventType::AxisChanged(axis, value));
+        app.app.update();
+    }
+
+    fn action_state(app: &AppBuilder) -> &ActionState {
+        app.app.world.get_resource::<ActionState>().unwrap()
+    }
+
+    #[test]
+    fn gamepads_are_tracked_from_their_events() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+        assert!(app.app.world.get_resource::<ConnectedGamepads>().unwrap().0.contains(&gamepad));
+
+        send(&mut app, gamepad, GamepadEventType::Disconnected);
+        app.app.update();
+        assert!(app.app.world.get_resource::<ConnectedGamepads>().unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn gamepad_buttons_trigger_their_actions() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+
+        press(&mut app, gamepad, GamepadButtonType::South, 1.0);
+        assert!(action_state(&app).just_pressed(Action::Confirm));
+
+        // Still held, it is no longer just pressed.
+        app.app.update();
+        assert!(!action_state(&app).just_pressed(Action::Confirm));
+        assert!(action_state(&app).pressed(Action::Confirm));
+
+        press(&mut app, gamepad, GamepadButtonType::South, 0.0);
+        assert!(!action_state(&app).pressed(Action::Confirm));
+    }
+
+    #[test]
+    fn buttons_follow_the_gamepad_settings_thresholds() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+
+        // Below the default press threshold of 0.75.
+        press(&mut app, gamepad, GamepadButtonType::South, 0.5);
+        assert!(!action_state(&app).pressed(Action::Confirm));
+
+        press(&mut app, gamepad, GamepadButtonType::South, 0.8);
+        assert!(action_state(&app).just_pressed(Action::Confirm));
+
+        // Held until it goes below the release threshold of 0.65.
+        press(&mut app, gamepad, GamepadButtonType::South, 0.7);
+        assert!(action_state(&app).pressed(Action::Confirm));
+        press(&mut app, gamepad, GamepadButtonType::South, 0.6);
+        assert!(!action_state(&app).pressed(Action::Confirm));
+    }
+
+    #[test]
+    fn actions_bound_only_to_a_gamepad_are_updated() {
+        let mut config = InputConfig::default();
+        config.gamepad.buttons.insert(Action::Quit, vec![GamepadButtonType::East]);
+        let mut input_map = InputMap::from_config(&config);
+        input_map.bindings.remove(&Action::Quit);
+
+        let mut app = app(input_map);
+        let gamepad = connect(&mut app);
+        press(&mut app, gamepad, GamepadButtonType::East, 1.0);
+        assert!(action_state(&app).just_pressed(Action::Quit));
+    }
+
+    #[test]
+    fn the_dpad_moves() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+        press(&mut app, gamepad, GamepadButtonType::DPadUp, 1.0);
+        assert!(action_state(&app).triggered(Action::MoveUp));
+    }
+
+    #[test]
+    fn the_left_stick_moves() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+        tilt(&mut app, gamepad, GamepadAxisType::LeftStickX, -1.0);
+        assert!(action_state(&app).triggered(Action::MoveLeft));
+
+        tilt(&mut app, gamepad, GamepadAxisType::LeftStickX, 0.0);
+        tilt(&mut app, gamepad, GamepadAxisType::LeftStickY, 0.98);
+        assert!(action_state(&app).triggered(Action::MoveUp));
+    }
+
+    #[test]
+    fn small_stick_tilts_dont_move() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+
+        // Inside bevy's own dead zone, the axis stays at 0.
+        tilt(&mut app, gamepad, GamepadAxisType::LeftStickX, 0.04);
+        let axes = app.app.world.get_resource::<Axis<GamepadAxis>>().unwrap();
+        assert_eq!(axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX)), Some(0.0));
+        assert!(!action_state(&app).pressed(Action::MoveRight));
+
+        // Then inside the dead zone of the input map.
+        tilt(&mut app, gamepad, GamepadAxisType::LeftStickX, default_map().deadzone / 2.0);
+        assert!(!action_state(&app).pressed(Action::MoveRight));
+    }
+
+    #[test]
+    fn disconnected_gamepads_are_ignored() {
+        let mut app = app(default_map());
+        let gamepad = connect(&mut app);
+        send(&mut app, gamepad, GamepadEventType::Disconnected);
+        app.app.update();
+
+        press(&mut app, gamepad, GamepadButtonType::South, 1.0);
+        assert!(!action_state(&app).just_pressed(Action::Confirm));
+    }
+
+    #[test]
+    fn sticks_snap_to_eight_directions() {
+        assert_eq!(stick_move(0.1, 0.2, 0.5), None);
+        assert_eq!(stick_move(1.0, 0.0, 0.5), Some(Action::MoveRight));
+        assert_eq!(stick_move(0.0, 1.0, 0.5), Some(Action::MoveUp));
+        assert_eq!(stick_move(-0.7, 0.7, 0.5), Some(Action::MoveUpLeft));
+        assert_eq!(stick_move(0.7, -0.7, 0.5), Some(Action::MoveDownRight));
+        assert_eq!(stick_move(-1.0, -0.1, 0.5), Some(Action::MoveLeft));
+    }
+
+    #[test]
+    fn held_actions_repeat_after_the_delay() {
+        let input_map = default_map();
+        let mut action_state = ActionState::default();
+
+        action_state.update(Action::MoveUp, true, 0.0, &input_map);
+        assert!(action_state.just_pressed(Action::MoveUp));
+        assert!(action_state.triggered(Action::MoveUp));
+
+        action_state.clear_frame();
+        action_state.update(Action::MoveUp, true, input_map.repeat_delay / 2.0, &input_map);
+        assert!(!action_state.triggered(Action::MoveUp));
+
+        action_state.clear_frame();
+        action_state.update(Action::MoveUp, true, input_map.repeat_delay, &input_map);
+        assert!(action_state.triggered(Action::MoveUp));
+        assert!(!action_state.just_pressed(Action::MoveUp));
+
+        action_state.clear_frame();
+        action_state.update(Action::MoveUp, false, 0.0, &input_map);
+        action_state.update(Action::MoveUp, true, 0.0, &input_map);
+        assert!(action_state.just_pressed(Action::MoveUp));
+    }
+
+    #[test]
+    fn consumed_presses_are_gone() {
+        let input_map = default_map();
+        let mut action_state = ActionState::default();
+        action_state.update(Action::Pause, true, 0.0, &input_map);
+
+        assert!(action_state.consume(Action::Pause));
+        assert!(!action_state.consume(Action::Pause));
+        assert!(!action_state.triggered(Action::Pause));
+    }
+}
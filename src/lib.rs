@@ -0,0 +1,15 @@
+pub mod ai;
+pub mod camera;
+pub mod combat;
+pub mod events;
+pub mod input;
+pub mod monster;
+pub mod picking;
+pub mod player;
+pub mod plugins;
+pub mod save;
+pub mod state;
+pub mod tooltip;
+pub mod travel;
+pub mod turn;
+pub mod world_seed;
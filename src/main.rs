@@ -1,15 +1,35 @@
 use bevy::{prelude::*, window::WindowMode};
 
-mod player;
-use player::*;
-
-mod events;
-use events::GameEvent;
-
-mod plugins;
-use crate::plugins::map::*;
+use bevy_tuto::ai::AiPlugin;
+use bevy_tuto::camera::CameraPlugin;
+use bevy_tuto::combat::CombatPlugin;
+use bevy_tuto::events::GameEvent;
+use bevy_tuto::input::{Action, ActionState, InputPlugin, InputSystem};
+use bevy_tuto::monster::MonsterPlugin;
+use bevy_tuto::picking::PickingPlugin;
+use bevy_tuto::player::*;
+use bevy_tuto::plugins::map::*;
+use bevy_tuto::plugins::map::map_config::MapConfig;
+use bevy_tuto::save::SavePlugin;
+use bevy_tuto::state::{AppState, StatePlugin};
+use bevy_tuto::tooltip::TooltipPlugin;
+use bevy_tuto::travel::TravelPlugin;
+use bevy_tuto::turn::{TurnPlugin, TurnSystem};
+use bevy_tuto::world_seed::WorldSeed;
 
 fn main() {
+    // `--export-map map.png` writes a preview of the world instead of playing.
+    if let Some(result) = export::export_from_args() {
+        match result {
+            Ok(path) => println!("Map exported to {}.", path),
+            Err(error) => {
+                eprintln!("Unable to export the map: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     App::build()
         .add_event::<GameEvent>()
         .insert_resource(WindowDescriptor {
@@ -21,29 +41,52 @@ fn main() {
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
+        // Replaced by the seed of the saved game, if any.
+        .insert_resource(WorldSeed::from_env())
+        // Before MapPlugin, a saved game brings back its seed.
+        .add_plugin(SavePlugin)
         .add_plugin(map_plugin::MapPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(StatePlugin)
+        .add_plugin(TurnPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(PickingPlugin)
+        .add_plugin(TooltipPlugin)
+        .add_plugin(TravelPlugin)
+        .add_plugin(MonsterPlugin)
+        .add_plugin(AiPlugin)
+        .add_plugin(CombatPlugin)
         .add_startup_system(setup.system())
-        .add_system(bevy::input::system::exit_on_esc_system.system())
-        .add_system(main_input_system.system())
-        .add_system(player_movement_system.system())
-        // .add_system(mouse_movement_updating_system.system())
-        // .add_system(get_tile_info_system.system())
+        .add_system_set(
+            SystemSet::on_update(AppState::Generating).with_system(player_start_system.system()),
+        )
+        .add_system(main_input_system.system().after(InputSystem::Update))
+        .add_system_set(
+            SystemSet::on_update(AppState::Playing).with_system(
+                player_movement_system
+                    .system()
+                    .label(PlayerSystem::Movement)
+                    .after(TurnSystem::Schedule)
+                    .after(InputSystem::Update),
+            ),
+        )
         .run();
 }
 
 fn setup(
-    mut commands: Commands,
+    commands: Commands,
+    config: Res<MapConfig>,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    spawn_player(commands, &asset_server, &mut materials);
+    spawn_player(commands, &config, &asset_server, &mut materials);
 }
 
 fn main_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    action_state: Res<ActionState>,
     mut windows: ResMut<Windows>,
 ) {  
-    if keyboard_input.just_pressed(KeyCode::F) {
+    if action_state.just_pressed(Action::ToggleFullscreen) {
         let window = windows.get_primary_mut().unwrap();
         let mode = window.mode();
         match mode {
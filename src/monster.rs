@@ -0,0 +1,490 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashSet},
+};
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::ai::Ai;
+use crate::combat::{Attack, Defense, Health};
+use crate::player::PlayerStart;
+use crate::plugins::map::coordinates::{tile_to_world, world_to_tile};
+use crate::plugins::map::fov::VisibleTiles;
+use crate::plugins::map::map_config::MapConfig;
+use crate::plugins::map::map_creator::{Map, TileType};
+use crate::plugins::map::map_plugin::MapState;
+use crate::plugins::map::pathfinding::Terrain;
+use crate::save::{LoadedGame, MonsterSave};
+use crate::state::world_exists;
+use crate::travel::InterruptsTravel;
+use crate::turn::TakesTurn;
+use crate::world_seed::{WorldSeed, MONSTER_SPAWN_STREAM};
+
+pub const MONSTER_DEFINITIONS_PATH: &str = "world.monsters.ron";
+
+// Monsters are drawn under the player.
+const MONSTER_Z: f32 = 4.0;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MonsterDefinition {
+    pub name: String,
+    // Sprite of the monster, a square of `color` is drawn when there is none.
+    #[serde(default)]
+    pub texture: Option<String>,
+    pub color: (f32, f32, f32),
+    pub health: i32,
+    pub attack: i32,
+    pub defense: i32,
+    pub speed: u32,
+    // Where the monster can go, the sea creatures swim.
+    #[serde(default)]
+    pub terrain: Terrain,
+}
+
+impl MonsterDefinition {
+    fn new(name: &str, color: (f32, f32, f32), health: i32, attack: i32, defense: i32, speed: u32) -> Self {
+        MonsterDefinition {
+            name: name.to_string(),
+            texture: None,
+            color,
+            health,
+            attack,
+            defense,
+            speed,
+            terrain: Terrain::Land,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SpawnEntry {
+    // Key of the monster in MonsterDefinitions::monsters.
+    pub monster: String,
+    pub weight: u32,
+}
+
+impl SpawnEntry {
+    fn new(monster: &str, weight: u32) -> Self {
+        SpawnEntry {
+            monster: monster.to_string(),
+            weight,
+        }
+    }
+}
+
+//
+// Monsters and where they live, loaded from world.monsters.ron. The default
+// values are the ones shipped in the asset.
+//
+#[derive(Debug, Clone, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "0d3f6a52-9c1e-4f0b-a8d4-3c2b7e5f9a61"]
+pub struct MonsterDefinitions {
+    // Chance for each walkable tile having a spawn table to get a monster.
+    pub density: f32,
+    // No monster spawns closer than this many tiles to the player's start.
+    pub safe_radius: i32,
+    pub monsters: HashMap<String, MonsterDefinition>,
+    // Monsters that can spawn on each tile type, with their relative weight.
+    pub spawn_tables: HashMap<TileType, Vec<SpawnEntry>>,
+}
+
+impl Default for MonsterDefinitions {
+    fn default() -> Self {
+        let mut monsters = HashMap::new();
+        monsters.insert("rat".to_string(), MonsterDefinition::new("giant rat", (0.55, 0.45, 0.35), 6, 2, 0, 12));
+        monsters.insert("goblin".to_string(), MonsterDefinition::new("goblin", (0.3, 0.6, 0.2), 10, 3, 1, 10));
+        monsters.insert("wolf".to_string(), MonsterDefinition::new("wolf", (0.5, 0.5, 0.55), 12, 4, 1, 14));
+        monsters.insert("bear".to_string(), MonsterDefinition::new("bear", (0.4, 0.25, 0.1), 24, 6, 2, 9));
+        monsters.insert("scorpion".to_string(), MonsterDefinition::new("giant scorpion", (0.8, 0.6, 0.2), 10, 5, 2, 10));
+        monsters.insert("hyena".to_string(), MonsterDefinition::new("hyena", (0.75, 0.65, 0.4), 10, 3, 1, 13));
+        monsters.insert(
+            "crab".to_string(),
+            MonsterDefinition {
+                terrain: Terrain::Any,
+                ..MonsterDefinition::new("giant crab", (0.85, 0.3, 0.2), 14, 3, 4, 8)
+            },
+        );
+        monsters.insert(
+            "shark".to_string(),
+            MonsterDefinition {
+                terrain: Terrain::Water,
+                ..MonsterDefinition::new("shark", (0.45, 0.5, 0.6), 16, 5, 1, 12)
+            },
+        );
+        monsters.insert("yeti".to_string(), MonsterDefinition::new("yeti", (0.9, 0.95, 1.0), 30, 7, 2, 10));
+
+        let mut spawn_tables = HashMap::new();
+        spawn_tables.insert(TileType::Dirt, vec![SpawnEntry::new("rat", 3), SpawnEntry::new("goblin", 1)]);
+        spawn_tables.insert(
+            TileType::Grass,
+            vec![SpawnEntry::new("rat", 2), SpawnEntry::new("goblin", 1), SpawnEntry::new("wolf", 1)],
+        );
+        spawn_tables.insert(TileType::Forest, vec![SpawnEntry::new("wolf", 2), SpawnEntry::new("bear", 1)]);
+        spawn_tables.insert(TileType::Sand, vec![SpawnEntry::new("scorpion", 1)]);
+        spawn_tables.insert(TileType::Savannah, vec![SpawnEntry::new("hyena", 2), SpawnEntry::new("scorpion", 1)]);
+        spawn_tables.insert(TileType::Shore, vec![SpawnEntry::new("crab", 1)]);
+        spawn_tables.insert(TileType::ShallowWater, vec![SpawnEntry::new("crab", 2), SpawnEntry::new("shark", 1)]);
+        spawn_tables.insert(TileType::DeepWater, vec![SpawnEntry::new("shark", 1)]);
+        spawn_tables.insert(TileType::Snow, vec![SpawnEntry::new("yeti", 1), SpawnEntry::new("wolf", 2)]);
+
+        MonsterDefinitions {
+            density: 0.015,
+            safe_radius: 6,
+            monsters,
+            spawn_tables,
+        }
+    }
+}
+
+impl MonsterDefinitions {
+    // Picks a monster of the tile's spawn table according to the weights.
+    fn pick<R: Rng>(&self, tile_type: TileType, rng: &mut R) -> Option<&str> {
+        let table = self.spawn_tables.get(&tile_type)?;
+        let total: u32 = table.iter().map(|entry| entry.weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = rng.gen_range(0..total);
+        for entry in table.iter() {
+            if roll < entry.weight {
+                return Some(entry.monster.as_str());
+            }
+            roll -= entry.weight;
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct MonsterDefinitionsLoader;
+
+impl AssetLoader for MonsterDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definitions = ron::de::from_bytes::<MonsterDefinitions>(bytes)?;
+            for entry in definitions.spawn_tables.values().flatten() {
+                if !definitions.monsters.contains_key(&entry.monster) {
+                    return Err(anyhow::anyhow!(
+                        "the spawn tables of {} use the unknown monster '{}'",
+                        MONSTER_DEFINITIONS_PATH,
+                        entry.monster
+                    ));
+                }
+            }
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["monsters.ron"]
+    }
+}
+
+#[derive(Default)]
+pub struct MonsterDefinitionsHandle(pub Handle<MonsterDefinitions>);
+
+// Set once the level is populated, with new or saved monsters.
+#[derive(Default)]
+pub struct MonstersSpawned(pub bool);
+
+//
+// Tiles taken by the monsters, kept up to date as they spawn, move and die.
+// The searches use them as blockers.
+//
+#[derive(Default)]
+pub struct OccupiedTiles(pub HashSet<(i32, i32)>);
+
+pub struct Monster {
+    // Key of the monster in MonsterDefinitions::monsters.
+    pub kind: String,
+    pub name: String,
+    pub terrain: Terrain,
+}
+
+#[derive(Bundle)]
+pub struct MonsterBundle {
+    pub monster: Monster,
+    pub health: Health,
+    pub attack: Attack,
+    pub defense: Defense,
+    pub takes_turn: TakesTurn,
+    pub ai: Ai,
+    pub interrupts_travel: InterruptsTravel,
+    #[bundle]
+    pub sprite: SpriteBundle,
+}
+
+impl MonsterBundle {
+    pub fn new(
+        kind: &str,
+        definition: &MonsterDefinition,
+        config: &MapConfig,
+        tile: (i32, i32),
+        material: Handle<ColorMaterial>,
+    ) -> MonsterBundle {
+        MonsterBundle {
+            monster: Monster {
+                kind: kind.to_string(),
+                name: definition.name.clone(),
+                terrain: definition.terrain,
+            },
+            health: Health::new(definition.health),
+            attack: Attack(definition.attack),
+            defense: Defense(definition.defense),
+            takes_turn: TakesTurn::new(definition.speed),
+            ai: Ai::default(),
+            interrupts_travel: InterruptsTravel,
+            sprite: SpriteBundle {
+                sprite: Sprite::new(Vec2::splat(config.tile_size as f32 * 0.75)),
+                material,
+                transform: Transform::from_translation(tile_to_world(config, tile, MONSTER_Z)),
+                // Hidden until the player sees its tile.
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            },
+        }
+    }
+}
+
+//
+// Picks the monsters of a level and their tiles. Every tile having a spawn
+// table, out of the safe radius around the start, rolls against the density.
+// The monster picked only stays if it can stand there, so land monsters listed
+// for the water are skipped. Tiles are visited in order so a seed always gives
+// the same monsters.
+//
+pub fn place_monsters<R: Rng>(
+    map: &Map,
+    definitions: &MonsterDefinitions,
+    start: (i32, i32),
+    rng: &mut R,
+) -> Vec<(String, (i32, i32))> {
+    let mut placements = Vec::new();
+
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            let tile_info = match map.get_tileinfo_at(x, y) {
+                Some(tile_info) => tile_info,
+                None => continue,
+            };
+            if !definitions.spawn_tables.contains_key(&tile_info.tile_type) {
+                continue;
+            }
+            if (x - start.0).abs().max((y - start.1).abs()) <= definitions.safe_radius {
+                continue;
+            }
+
+            if rng.gen::<f32>() < definitions.density {
+                if let Some(kind) = definitions.pick(tile_info.tile_type, rng) {
+                    let can_stand = definitions
+                        .monsters
+                        .get(kind)
+                        .map_or(false, |definition| definition.terrain.allows(&tile_info));
+                    if can_stand {
+                        placements.push((kind.to_string(), (x, y)));
+                    }
+                }
+            }
+        }
+    }
+
+    placements
+}
+
+pub fn load_monster_definitions(
+    mut handle: ResMut<MonsterDefinitionsHandle>,
+    asset_server: Res<AssetServer>,
+) {
+    handle.0 = asset_server.load(MONSTER_DEFINITIONS_PATH);
+}
+
+//
+// Populates the level once its terrain is spawned. Waits for the definitions
+// so the monsters don't depend on how fast the asset loads.
+//
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_monsters_system(
+    mut commands: Commands,
+    mut monsters_spawned: ResMut<MonstersSpawned>,
+    mut occupied: ResMut<OccupiedTiles>,
+    config: Res<MapConfig>,
+    world_seed: Res<WorldSeed>,
+    map: Res<Map>,
+    map_state: Res<MapState>,
+    asset_server: Res<AssetServer>,
+    handle: Res<MonsterDefinitionsHandle>,
+    monster_assets: Res<Assets<MonsterDefinitions>>,
+    mut definitions: ResMut<MonsterDefinitions>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut loaded_game: Option<ResMut<LoadedGame>>,
+    player_start: Res<PlayerStart>,
+) {
+    if monsters_spawned.0 || !map_state.spawned {
+        return;
+    }
+
+    match asset_server.get_load_state(&handle.0) {
+        LoadState::Loaded => {
+            if let Some(loaded) = monster_assets.get(&handle.0) {
+                *definitions = loaded.clone();
+            }
+        }
+        LoadState::Failed => warn!("Unable to load {}, using the default monsters.", MONSTER_DEFINITIONS_PATH),
+        _ => return,
+    }
+
+    let start = match player_start.0 {
+        Some(start) => start,
+        None => return,
+    };
+
+    // A loaded game brings back its own monsters.
+    let saved_monsters = loaded_game.as_mut().and_then(|loaded_game| loaded_game.monsters.take());
+    let placements: Vec<(String, (i32, i32), Option<MonsterSave>)> = match saved_monsters {
+        Some(saved_monsters) => saved_monsters
+            .into_iter()
+            .map(|saved| (saved.kind.clone(), saved.tile, Some(saved)))
+            .collect(),
+        None => {
+            let mut rng = world_seed.rng(MONSTER_SPAWN_STREAM);
+            place_monsters(&map, &definitions, start, &mut rng)
+                .into_iter()
+                .map(|(kind, tile)| (kind, tile, None))
+                .collect()
+        }
+    };
+
+    occupied.0.clear();
+    let mut monster_materials: HashMap<String, Handle<ColorMaterial>> = HashMap::new();
+    for (kind, tile, saved) in placements.iter() {
+        let definition = match definitions.monsters.get(kind) {
+            Some(definition) => definition,
+            None => {
+                warn!("Unknown monster '{}' in the saved game.", kind);
+                continue;
+            }
+        };
+        let material = monster_materials
+            .entry(kind.clone())
+            .or_insert_with(|| {
+                let (r, g, b) = definition.color;
+                let color = Color::rgb(r, g, b);
+                match &definition.texture {
+                    Some(texture) => {
+                        let texture_handle = asset_server.load(texture.as_str());
+                        materials.add(ColorMaterial::modulated_texture(texture_handle, color))
+                    }
+                    None => materials.add(ColorMaterial::color(color)),
+                }
+            })
+            .clone();
+
+        let mut bundle = MonsterBundle::new(kind, definition, &config, *tile, material);
+        if let Some(saved) = saved {
+            bundle.health = saved.health;
+            bundle.takes_turn.energy = saved.energy;
+        }
+        commands.spawn_bundle(bundle);
+        occupied.0.insert(*tile);
+    }
+
+    info!("Spawned {} monsters.", placements.len());
+    monsters_spawned.0 = true;
+}
+
+// Only the monsters on a tile the player sees are drawn.
+pub fn monster_visibility_system(
+    config: Res<MapConfig>,
+    visible_tiles: Res<VisibleTiles>,
+    mut query: Query<(&Transform, &mut Visible), With<Monster>>,
+) {
+    for (transform, mut visible) in query.iter_mut() {
+        let (x, y) = world_to_tile(&config, &transform.translation);
+        let in_view = visible_tiles.contains(x, y);
+        if visible.is_visible != in_view {
+            visible.is_visible = in_view;
+        }
+    }
+}
+
+pub struct MonsterPlugin;
+
+impl Plugin for MonsterPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MonsterDefinitions>()
+            .init_resource::<MonsterDefinitionsHandle>()
+            .init_resource::<MonstersSpawned>()
+            .init_resource::<OccupiedTiles>()
+            .init_resource::<PlayerStart>()
+            .add_asset::<MonsterDefinitions>()
+            .init_asset_loader::<MonsterDefinitionsLoader>()
+            .add_startup_system(load_monster_definitions.system())
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_exists.system())
+                    .with_system(spawn_monsters_system.system())
+                    .with_system(monster_visibility_system.system()),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn definitions(tile_type: TileType, monster: &str) -> MonsterDefinitions {
+        let mut definitions = MonsterDefinitions::default();
+        definitions.density = 1.0;
+        definitions.safe_radius = 0;
+        definitions.spawn_tables.clear();
+        definitions.spawn_tables.insert(tile_type, vec![SpawnEntry::new(monster, 1)]);
+        definitions
+    }
+
+    fn placed_tiles(map: &Map, definitions: &MonsterDefinitions) -> Vec<(i32, i32)> {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        place_monsters(map, definitions, (-10, -10), &mut rng)
+            .into_iter()
+            .map(|(_, tile)| tile)
+            .collect()
+    }
+
+    #[test]
+    fn swimmers_spawn_in_deep_water() {
+        let map = Map::from_rows(&["~~..", "~w.."]);
+        let tiles = placed_tiles(&map, &definitions(TileType::DeepWater, "shark"));
+        assert_eq!(tiles, vec![(0, 0), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn land_monsters_never_spawn_in_the_water() {
+        let map = Map::from_rows(&["~~..", "~w.."]);
+        assert!(placed_tiles(&map, &definitions(TileType::DeepWater, "rat")).is_empty());
+        assert_eq!(placed_tiles(&map, &definitions(TileType::Grass, "rat")).len(), 4);
+    }
+
+    #[test]
+    fn no_monster_spawns_in_the_safe_radius() {
+        let map = Map::from_rows(&["....", "...."]);
+        let mut definitions = definitions(TileType::Grass, "wolf");
+        definitions.safe_radius = 1;
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let placements = place_monsters(&map, &definitions, (0, 0), &mut rng);
+        let tiles: Vec<(i32, i32)> = placements.into_iter().map(|(_, tile)| tile).collect();
+        assert_eq!(tiles, vec![(2, 0), (3, 0), (2, 1), (3, 1)]);
+    }
+}
@@ -0,0 +1,187 @@
+use bevy::{prelude::*, render::camera::OrthographicProjection};
+
+use crate::camera::MainCamera;
+use crate::events::TileInspected;
+use crate::plugins::map::coordinates::world_to_tile;
+use crate::plugins::map::map_config::MapConfig;
+use crate::plugins::map::map_creator::Map;
+use crate::state::AppState;
+
+//
+// Where the cursor is, refreshed every frame so it stays right while the
+// camera scrolls under a still cursor.
+//
+#[derive(Default)]
+pub struct MouseLocation {
+    // Last cursor position in the window, origin at the bottom left.
+    pub screen: Option<Vec2>,
+    pub world: Option<Vec2>,
+    pub tile: Option<(i32, i32)>,
+}
+
+//
+// Converts a window position into a world position through the camera
+// projection, which is kept in sync with the window size by bevy, and the
+// camera transform.
+//
+pub fn screen_to_world(
+    screen: Vec2,
+    window_size: Vec2,
+    projection: &OrthographicProjection,
+    camera_transform: &Transform,
+) -> Vec2 {
+    let ratio = screen / window_size;
+    let projected = Vec3::new(
+        (projection.left + ratio.x * (projection.right - projection.left)) * projection.scale,
+        (projection.bottom + ratio.y * (projection.top - projection.bottom)) * projection.scale,
+        0.0,
+    );
+
+    camera_transform.mul_vec3(projected).truncate()
+}
+
+pub fn mouse_location_system(
+    config: Res<MapConfig>,
+    windows: Res<Windows>,
+    mut cursor_moved_events: EventReader<CursorMoved>,
+    mut mouse_location: ResMut<MouseLocation>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    for event in cursor_moved_events.iter() {
+        if event.id == window.id() {
+            mouse_location.screen = Some(event.position);
+        }
+    }
+
+    // The cursor left the window.
+    if window.cursor_position().is_none() {
+        mouse_location.screen = None;
+    }
+
+    let screen = match mouse_location.screen {
+        Some(screen) => screen,
+        None => {
+            mouse_location.world = None;
+            mouse_location.tile = None;
+            return;
+        }
+    };
+
+    let window_size = Vec2::new(window.width(), window.height());
+    for (camera_transform, projection) in camera_query.iter() {
+        let world = screen_to_world(screen, window_size, projection, camera_transform);
+        mouse_location.world = Some(world);
+        mouse_location.tile = Some(world_to_tile(&config, &world.extend(0.0)));
+    }
+}
+
+//
+// Sends a TileInspected event when a map tile is clicked.
+//
+pub fn tile_inspection_system(
+    mouse_button_input: Res<Input<MouseButton>>,
+    mouse_location: Res<MouseLocation>,
+    map: Res<Map>,
+    mut events: EventWriter<TileInspected>,
+) {
+    if !mouse_button_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    if let Some((x, y)) = mouse_location.tile {
+        if let Some(tile_info) = map.get_tileinfo_at(x, y) {
+            info!("The terrain type is {}", tile_info.tile_type);
+            events.send(TileInspected {
+                tile: (x, y),
+                tile_info,
+            });
+        }
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PickingSystem {
+    MouseLocation,
+}
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MouseLocation>()
+            .add_event::<TileInspected>()
+            .add_system(mouse_location_system.system().label(PickingSystem::MouseLocation))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(tile_inspection_system.system().after(PickingSystem::MouseLocation)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::CameraProjection;
+
+    // Projection of an 800x600 window, as bevy keeps it in sync with the window.
+    fn projection(scale: f32) -> OrthographicProjection {
+        let mut projection = OrthographicProjection {
+            scale,
+            ..Default::default()
+        };
+        projection.update(800.0, 600.0);
+        projection
+    }
+
+    #[test]
+    fn the_window_center_is_the_camera_position() {
+        let window_size = Vec2::new(800.0, 600.0);
+        let camera_transform = Transform::from_xyz(100.0, 50.0, 999.0);
+        let world = screen_to_world(Vec2::new(400.0, 300.0), window_size, &projection(1.0), &camera_transform);
+        assert_eq!(world, Vec2::new(100.0, 50.0));
+
+        // The window origin is at its bottom left corner.
+        let world = screen_to_world(Vec2::ZERO, window_size, &projection(1.0), &camera_transform);
+        assert_eq!(world, Vec2::new(-300.0, -250.0));
+    }
+
+    #[test]
+    fn the_projection_scale_zooms_around_the_camera() {
+        let window_size = Vec2::new(800.0, 600.0);
+        let camera_transform = Transform::from_xyz(100.0, 50.0, 999.0);
+        let world = screen_to_world(window_size, window_size, &projection(2.0), &camera_transform);
+        assert_eq!(world, Vec2::new(900.0, 650.0));
+        let world = screen_to_world(Vec2::new(400.0, 300.0), window_size, &projection(2.0), &camera_transform);
+        assert_eq!(world, Vec2::new(100.0, 50.0));
+    }
+
+    #[test]
+    fn the_last_row_and_column_are_picked() {
+        // 4x3 tiles map seen whole, in a window of its size.
+        let config = MapConfig {
+            width: 4,
+            height: 3,
+            tile_size: 32,
+            chunk_width: 2,
+            chunk_height: 2,
+        };
+        let window_size = Vec2::new(128.0, 96.0);
+        let mut projection = OrthographicProjection::default();
+        projection.update(window_size.x, window_size.y);
+        let camera_transform = Transform::from_xyz(64.0, 48.0, 999.0);
+
+        let tile_at = |screen: Vec2| {
+            let world = screen_to_world(screen, window_size, &projection, &camera_transform);
+            world_to_tile(&config, &world.extend(0.0))
+        };
+        assert_eq!(tile_at(Vec2::new(0.5, 0.5)), (0, 0));
+        assert_eq!(tile_at(Vec2::new(127.5, 95.5)), (3, 2));
+        assert_eq!(tile_at(Vec2::new(127.5, 0.5)), (3, 0));
+        assert_eq!(tile_at(Vec2::new(0.5, 95.5)), (0, 2));
+    }
+}
@@ -1,138 +1,232 @@
-use bevy::{
-    input::{mouse::MouseButtonInput, ElementState},
-    prelude::*,
-    render::camera::{OrthographicProjection, WindowOrigin},
-};
-use bevy::app::Events;
-
+use bevy::prelude::*;
+use rand::Rng;
 
+use crate::camera::{clamp_to_map, view_size, MainCamera};
+use crate::combat::{Attack, Defense, Health};
+use crate::events::MeleeAttack;
+use crate::input::{Action, ActionState};
+use crate::monster::Monster;
+use crate::save::LoadedGame;
+use crate::plugins::map::coordinates::{tile_to_world, world_to_tile};
+use crate::plugins::map::map_config::MapConfig;
 use crate::plugins::map::map_creator::Map;
-use crate::plugins::map::map_creator::TILE_SIZE;
+use crate::travel::StepAnimation;
+use crate::turn::{TakesTurn, TurnQueue, ACTION_COST, NORMAL_SPEED};
+use crate::world_seed::{WorldSeed, PLAYER_START_STREAM};
+
+pub const PLAYER_HEALTH: i32 = 30;
+pub const PLAYER_ATTACK: i32 = 5;
+pub const PLAYER_DEFENSE: i32 = 1;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Direction {
     Left,
     Right,
     Up,
     Down,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
     Idle,
 }
 
+impl Direction {
+    // Tile offset of a step in this direction, y going up.
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+            Direction::UpLeft => (-1, 1),
+            Direction::UpRight => (1, 1),
+            Direction::DownLeft => (-1, -1),
+            Direction::DownRight => (1, -1),
+            Direction::Idle => (0, 0),
+        }
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PlayerSystem {
+    Movement,
+}
+
 pub struct Player {
     pub speed: f32,
     pub direction: Direction,
 }
 
-// pub struct CursorState {
-//     /*
-//     https://bevy-cheatbook.github.io/cookbook/cursor2world.html
-//     https://discord.com/channels/691052431525675048/692648082499829760/835750090366255154
-//     */
-//     pub cursor: EventReader<CursorMoved>,
-//     pub button: EventReader<MouseButtonInput>,
-//     pub camera_e: Entity,
-// }
-pub struct MouseLocation(Vec2);
+//
+// Tile the player starts on, chosen once the map is generated. The monsters
+// keep their distance from it.
+//
+#[derive(Default)]
+pub struct PlayerStart(pub Option<(i32, i32)>);
+
+//
+// One of the walkable tiles nearest to the center of the map, None when no
+// tile is walkable.
+//
+pub fn choose_start<R: Rng>(map: &Map, rng: &mut R) -> Option<(i32, i32)> {
+    let center = (map.width as i32 / 2, map.height as i32 / 2);
+    let mut nearest: Vec<(i32, i32)> = Vec::new();
+    let mut nearest_distance = i32::MAX;
+
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            if !map.get_tileinfo_at(x, y).map_or(false, |tile_info| tile_info.walkable) {
+                continue;
+            }
+
+            let distance = (x - center.0).pow(2) + (y - center.1).pow(2);
+            if distance < nearest_distance {
+                nearest_distance = distance;
+                nearest.clear();
+            }
+            if distance == nearest_distance {
+                nearest.push((x, y));
+            }
+        }
+    }
+
+    if nearest.is_empty() {
+        None
+    } else {
+        Some(nearest[rng.gen_range(0..nearest.len())])
+    }
+}
+
+//
+// Chooses the start once the map is generated and moves the player there,
+// unless a saved game put the player back where it was.
+//
+#[allow(clippy::too_many_arguments)]
+pub fn player_start_system(
+    config: Res<MapConfig>,
+    world_seed: Res<WorldSeed>,
+    map: Res<Map>,
+    windows: Res<Windows>,
+    loaded_game: Option<Res<LoadedGame>>,
+    mut player_start: ResMut<PlayerStart>,
+    mut player_query: Query<&mut Transform, With<Player>>,
+    mut camera_query: Query<&mut Transform, (With<MainCamera>, Without<Player>)>,
+) {
+    if player_start.0.is_some() || !map.is_generated() {
+        return;
+    }
+
+    let mut rng = world_seed.rng(PLAYER_START_STREAM);
+    let start = match choose_start(&map, &mut rng) {
+        Some(start) => start,
+        None => {
+            warn!("No walkable tile to start on.");
+            (map.width as i32 / 2, map.height as i32 / 2)
+        }
+    };
+    player_start.0 = Some(start);
+
+    if loaded_game.is_some() {
+        return;
+    }
+
+    for mut transform in player_query.iter_mut() {
+        transform.translation = tile_to_world(&config, start, transform.translation.z);
+
+        if let Some(view_size) = view_size(&windows) {
+            let center = clamp_to_map(transform.translation.truncate(), view_size, &config);
+            for mut camera_transform in camera_query.iter_mut() {
+                camera_transform.translation.x = center.x;
+                camera_transform.translation.y = center.y;
+            }
+        }
+    }
+}
 
 pub fn spawn_player(
     mut commands: Commands,
+    config: &MapConfig,
     asset_server: &Res<AssetServer>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
 ) {
     let mut camera = OrthographicCameraBundle::new_2d();
     camera.transform = Transform::from_translation(Vec3::new(0.0, 0.0, 5.0));
-    commands.spawn_bundle(camera);
+    commands.spawn_bundle(camera).insert(MainCamera);
 
     let texture_handle = asset_server.load("unseen_horror_new.png");
     commands
         .spawn_bundle(SpriteBundle {
             material: materials.add(texture_handle.into()),
-            transform: Transform::from_translation(Vec3::new(
-                TILE_SIZE as f32 / 2.0,
-                TILE_SIZE as f32 / 2.0,
-                5.0,
-            )),
+            // Moved to the PlayerStart once the map is generated.
+            transform: Transform::from_translation(tile_to_world(config, (0, 0), 5.0)),
             ..Default::default()
         })
         .insert(Player {
-            speed: TILE_SIZE as f32,
+            speed: config.tile_size as f32,
             direction: Direction::Idle,
-        });
-
-    commands.insert_resource(MouseLocation(Vec2::new(0.0, 0.0)));
+        })
+        .insert(TakesTurn::new(NORMAL_SPEED))
+        .insert(Health::new(PLAYER_HEALTH))
+        .insert(Attack(PLAYER_ATTACK))
+        .insert(Defense(PLAYER_DEFENSE));
 }
 
-// pub fn mouse_movement_updating_system(
-//     mut mouse_pos: ResMut<MouseLocation>,
-//     mut state: ResMut<CursorState>,
-//     cursor_moved_events: Res<Events<CursorMoved>>,
-// ) {
-//     for event in state.cursor.iter() {
-//         mouse_pos.0 = transform_pos_to_map_pos(&event.position.extend(5.0)).truncate();
-//     }
-// }
-
-// pub fn get_tile_info_system(
-//     ev_button: Res<Events<MouseButtonInput>>,
-//     map: Res<Map>,
-//     mouse_pos: Res<MouseLocation>,
-//     mut state: ResMut<CursorState>,
-// ) {
-//     for event in state.button.iter() {
-//         if event.state == ElementState::Pressed {
-//             let tile_info = map.get_tileinfo_at(mouse_pos.0.x as usize, mouse_pos.0.y as usize);
-//             info!("The terrain type is {}", tile_info.tile_type);
-//         }
-//     }
-// }
-
-// fn transform_pos_to_map_pos(position: &Vec3) -> Vec3 {
-//     Vec3::new(
-//         (position.x / TILE_SIZE as f32).floor(),
-//         (position.y / TILE_SIZE as f32).floor(),
-//         5.0,
-//     )
-// }
-
 pub fn player_movement_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    windows: Res<Windows>,
+    action_state: Res<ActionState>,
+    config: Res<MapConfig>,
     map: Res<Map>,
-    mut query: Query<(&mut Player, &mut Transform)>,
+    mut turn_queue: ResMut<TurnQueue>,
+    mut attacks: EventWriter<MeleeAttack>,
+    mut query: Query<(Entity, &mut Player, &mut Transform, &mut TakesTurn), Without<StepAnimation>>,
+    monsters: Query<(Entity, &Transform), (With<Monster>, Without<Player>)>,
 ) {
-    for (mut player, mut transform) in query.iter_mut() {
-        let translation = &mut transform.translation;
-        let mut player_destination: Vec3 = Vec2::ZERO.extend(5.0);
-
-        if keyboard_input.just_pressed(KeyCode::Left) {
-            player.direction = Direction::Left;
-            player_destination.x = translation.x + player.speed * -1.0;
-            player_destination.y = translation.y;
-        } else if keyboard_input.just_pressed(KeyCode::Right) {
-            player.direction = Direction::Right;
-            player_destination.x = translation.x + player.speed * 1.0;
-            player_destination.y = translation.y;
-        } else if keyboard_input.just_pressed(KeyCode::Up) {
-            player.direction = Direction::Up;
-            player_destination.x = translation.x;
-            player_destination.y = translation.y + player.speed * 1.0;
-        } else if keyboard_input.just_pressed(KeyCode::Down) {
-            player.direction = Direction::Down;
-            player_destination.x = translation.x;
-            player_destination.y = translation.y + player.speed * -1.0;
-        } else {
-            player.direction = Direction::Idle;
+    for (entity, mut player, mut transform, mut takes_turn) in query.iter_mut() {
+        // The player can only act on its own turn.
+        if !turn_queue.is_turn_of(entity) {
+            continue;
+        }
+
+        // Held keys trigger again after the repeat delay.
+        player.direction = Action::MOVES
+            .iter()
+            .find(|action| action_state.triggered(**action))
+            .and_then(|action| action.direction())
+            .unwrap_or(Direction::Idle);
+        if player.direction == Direction::Idle {
             return;
         }
 
-        let active_window = windows.get_primary().unwrap();
+        let translation = &mut transform.translation;
+        let (dx, dy) = player.direction.offset();
+        let player_destination = Vec3::new(
+            translation.x + player.speed * dx as f32,
+            translation.y + player.speed * dy as f32,
+            translation.z,
+        );
+
+        // Moving into a monster attacks it.
+        let destination_tile = world_to_tile(&config, &player_destination);
+        let target = monsters
+            .iter()
+            .find(|(_, monster_transform)| world_to_tile(&config, &monster_transform.translation) == destination_tile);
+        if let Some((monster, _)) = target {
+            attacks.send(MeleeAttack {
+                attacker: entity,
+                target: monster,
+            });
+            turn_queue.end_turn(&mut takes_turn, ACTION_COST);
+            return;
+        }
 
         let movement: (bool, bool) =
-            validate_movement(&player_destination, &player.direction, &map, &active_window);
+            validate_movement(&player_destination, &player.direction, &config, &map);
         if movement.0 {
             if movement.1 {
                 //Movement is legal, proceed.
                 translation.x = player_destination.x;
                 translation.y = player_destination.y;
+                turn_queue.end_turn(&mut takes_turn, ACTION_COST);
             } else {
                 info!("Movement was illegal...");
                 return;
@@ -147,50 +241,142 @@ pub fn player_movement_system(
 fn validate_movement(
     player_destination: &Vec3,
     direction: &Direction,
-    map: &Res<Map>,
-    window: &Window,
+    config: &MapConfig,
+    map: &Map,
 ) -> (bool, bool) {
-    let mut screen_movement_legal = false;
-    let mut map_terrain_movement_legal = false;
-
-    // First, check if the player wants to move outside the game screen.
-    match direction {
-        Direction::Left => {
-            if player_destination.x < 0.0 {
-                screen_movement_legal = false;
-            } else {
-                screen_movement_legal = true;
-            }
-        }
-        Direction::Right => {
-            if player_destination.x < window.width() as f32 {
-                screen_movement_legal = true;
-            }
-        }
-        Direction::Up => {
-            if player_destination.y < window.height() as f32 {
-                screen_movement_legal = true;
-            }
+    // First, check if the player wants to move outside the map. Moving past the
+    // screen edge is fine, the camera follows.
+    let map_bounds_movement_legal = *direction != Direction::Idle
+        && player_destination.x >= 0.0
+        && player_destination.x < config.world_width()
+        && player_destination.y >= 0.0
+        && player_destination.y < config.world_height();
+
+    if !map_bounds_movement_legal {
+        return (false, false);
+    }
+
+    // Second, check whether the ground tile is walkable at the player_destination.
+    // A destination outside of the map is never walkable. Like the pathfinding,
+    // a diagonal step can't cut the corner of an unwalkable tile.
+    let is_walkable = |(tile_x, tile_y): (i32, i32)| match map.get_tileinfo_at(tile_x, tile_y) {
+        Some(tile_info) => tile_info.walkable,
+        None => false,
+    };
+    let (tile_x, tile_y) = world_to_tile(config, player_destination);
+    let (dx, dy) = direction.offset();
+    let map_terrain_movement_legal = is_walkable((tile_x, tile_y))
+        && (dx == 0 || dy == 0 || (is_walkable((tile_x - dx, tile_y)) && is_walkable((tile_x, tile_y - dy))));
+
+    (map_bounds_movement_legal, map_terrain_movement_legal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRECTIONS: [Direction; 8] = [
+        Direction::Left,
+        Direction::Right,
+        Direction::Up,
+        Direction::Down,
+        Direction::UpLeft,
+        Direction::UpRight,
+        Direction::DownLeft,
+        Direction::DownRight,
+    ];
+
+    fn config() -> MapConfig {
+        MapConfig {
+            width: 3,
+            height: 3,
+            tile_size: 32,
+            chunk_width: 16,
+            chunk_height: 16,
         }
-        Direction::Down => {
-            if player_destination.y > 0.0 {
-                screen_movement_legal = true;
-            }
+    }
+
+    fn step(config: &MapConfig, map: &Map, from: (i32, i32), direction: Direction) -> (bool, bool) {
+        let (dx, dy) = direction.offset();
+        let destination = tile_to_world(config, (from.0 + dx, from.1 + dy), 0.0);
+        validate_movement(&destination, &direction, config, map)
+    }
+
+    #[test]
+    fn every_direction_is_legal_on_open_ground() {
+        let config = config();
+        let map = Map::from_rows(&["...", "...", "..."]);
+        for direction in DIRECTIONS.iter() {
+            assert_eq!(step(&config, &map, (1, 1), *direction), (true, true), "{:?}", direction);
         }
-        Direction::Idle => {}
     }
 
-    if !screen_movement_legal {
-        return (false, false);
+    #[test]
+    fn idle_is_never_a_movement() {
+        let config = config();
+        let map = Map::from_rows(&["...", "...", "..."]);
+        assert_eq!(step(&config, &map, (1, 1), Direction::Idle), (false, false));
     }
 
-    // Second, check whether the ground tile is walkable at the player_destination.
-    // let map_pos = transform_pos_to_map_pos(&player_destination);
-    // let tile_info = map.get_tileinfo_at(map_pos.x as usize, map_pos.y as usize);
-    // map_terrain_movement_legal = tile_info.walkable;
+    #[test]
+    fn negative_coordinates_are_outside_the_map() {
+        let config = config();
+        let map = Map::from_rows(&["...", "...", "..."]);
+        assert_eq!(step(&config, &map, (0, 0), Direction::Left), (false, false));
+        assert_eq!(step(&config, &map, (0, 0), Direction::Down), (false, false));
+        assert_eq!(step(&config, &map, (0, 1), Direction::DownLeft), (false, false));
+        assert_eq!(step(&config, &map, (1, 0), Direction::DownRight), (false, false));
+    }
 
-    // Thirdly, check if the next step is outside the map. If so, send an event 
-    // so that the map scroll in this direction
+    #[test]
+    fn width_and_height_are_outside_the_map() {
+        let config = config();
+        let map = Map::from_rows(&["...", "...", "..."]);
+        // x == width
+        assert_eq!(step(&config, &map, (2, 1), Direction::Right), (false, false));
+        // y == height
+        assert_eq!(step(&config, &map, (1, 2), Direction::Up), (false, false));
+        assert_eq!(step(&config, &map, (2, 2), Direction::UpRight), (false, false));
+        // The last column and row are inside.
+        assert_eq!(step(&config, &map, (1, 2), Direction::Right), (true, true));
+        assert_eq!(step(&config, &map, (2, 1), Direction::Up), (true, true));
+    }
+
+    #[test]
+    fn unwalkable_tiles_block_the_movement() {
+        let config = config();
+        let map = Map::from_rows(&["...", "..#", "..."]);
+        assert_eq!(step(&config, &map, (1, 1), Direction::Right), (true, false));
+        assert_eq!(step(&config, &map, (1, 1), Direction::Left), (true, true));
+    }
+
+    #[test]
+    fn diagonals_cant_cut_corners() {
+        let config = config();
+        let map = Map::from_rows(&["...", ".#.", "..."]);
+        assert_eq!(step(&config, &map, (0, 1), Direction::UpRight), (true, false));
+        assert_eq!(step(&config, &map, (0, 0), Direction::UpRight), (true, false));
+        assert_eq!(step(&config, &map, (0, 2), Direction::Up), (false, false));
+        assert_eq!(step(&config, &map, (0, 1), Direction::UpLeft), (false, false));
+        assert_eq!(step(&config, &map, (0, 0), Direction::Up), (true, true));
+    }
 
-    (screen_movement_legal, map_terrain_movement_legal)
+    #[test]
+    fn the_start_is_the_nearest_land_to_the_center() {
+        // The center (3, 2) is in the sea, two islands are as close to it.
+        let map = Map::from_rows(&["~~~~~~~", "~~~~~.~", "~~~~~~~", "~.~~~~~", "~~~~~~."]);
+        for seed in 0..8 {
+            let start = choose_start(&map, &mut WorldSeed(seed).rng(PLAYER_START_STREAM)).unwrap();
+            assert!(start == (5, 1) || start == (1, 3), "{:?}", start);
+            assert!(map.get_tileinfo_at(start.0, start.1).unwrap().walkable);
+            // The same seed gives the same start.
+            assert_eq!(choose_start(&map, &mut WorldSeed(seed).rng(PLAYER_START_STREAM)), Some(start));
+        }
+    }
+
+    #[test]
+    fn there_is_no_start_without_land() {
+        let map = Map::from_rows(&["~~~", "~w~", "~~~"]);
+        assert_eq!(choose_start(&map, &mut WorldSeed(0).rng(PLAYER_START_STREAM)), None);
+    }
 }
@@ -0,0 +1,53 @@
+use super::definitions::BiomeDefinitions;
+use super::map_creator::TileType;
+
+//
+// Temperature of a tile from its noise value, its latitude and elevation. The
+// map equator is its middle row, the poles are the top and bottom rows.
+//
+pub fn temperature(
+    noise: f32,
+    y: usize,
+    height: usize,
+    elevation: f32,
+    definitions: &BiomeDefinitions,
+) -> f32 {
+    let latitude = if height > 1 {
+        y as f32 / (height - 1) as f32
+    } else {
+        0.5
+    };
+    let equator_distance = (2.0 * latitude - 1.0).abs();
+    let base = definitions.latitude_weight * (1.0 - equator_distance)
+        + (1.0 - definitions.latitude_weight) * noise;
+    let land_height = (elevation - definitions.shore_level).max(0.0) / (1.0 - definitions.shore_level);
+    let cooling = definitions.altitude_cooling * land_height;
+
+    (base - cooling).max(0.0).min(1.0)
+}
+
+pub fn biome(elevation: f32, moisture: f32, temperature: f32, definitions: &BiomeDefinitions) -> TileType {
+    if elevation < definitions.deep_water_level {
+        TileType::DeepWater
+    } else if elevation < definitions.shallow_water_level {
+        TileType::ShallowWater
+    } else if elevation < definitions.shore_level {
+        TileType::Shore
+    } else if elevation >= definitions.mountain_level {
+        TileType::Mountain
+    } else if elevation >= definitions.rock_level {
+        if temperature < definitions.snow_line_temperature {
+            TileType::Snow
+        } else {
+            TileType::Rock
+        }
+    } else {
+        let temperature_band = &definitions.table[band(temperature, definitions.table.len())];
+        temperature_band[band(moisture, temperature_band.len())]
+    }
+}
+
+// Splits a [0, 1] value in the given number of bands.
+fn band(value: f32, bands: usize) -> usize {
+    ((value * bands as f32) as usize).min(bands - 1)
+}
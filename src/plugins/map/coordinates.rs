@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+
+use super::map_config::MapConfig;
+
+//
+// Conversions between the three coordinate spaces used by the map:
+//  - world: the Transform translation of an entity, in pixels.
+//  - tile: the (x, y) index of a tile inside Map::level_data.
+//  - chunk: the (x, y) index of the tilemap chunk holding a tile.
+//
+// Tile (0, 0) covers the world square [0, tile_size[ on both axis, so its
+// center sits at (tile_size / 2, tile_size / 2). Tile coordinates are also the
+// points used by the Tilemap, whose chunks are centered on their own origin:
+// chunk (0, 0) spans the tiles -chunk_width / 2 to chunk_width / 2 - 1.
+//
+
+pub fn world_to_tile(config: &MapConfig, position: &Vec3) -> (i32, i32) {
+    (
+        (position.x / config.tile_size as f32).floor() as i32,
+        (position.y / config.tile_size as f32).floor() as i32,
+    )
+}
+
+pub fn tile_to_world(config: &MapConfig, tile: (i32, i32), z: f32) -> Vec3 {
+    let tile_size = config.tile_size as f32;
+    Vec3::new(
+        tile.0 as f32 * tile_size + tile_size / 2.0,
+        tile.1 as f32 * tile_size + tile_size / 2.0,
+        z,
+    )
+}
+
+pub fn tile_to_chunk(config: &MapConfig, tile: (i32, i32)) -> (i32, i32) {
+    let chunk_width = config.chunk_width as i32;
+    let chunk_height = config.chunk_height as i32;
+    (
+        (tile.0 + chunk_width / 2).div_euclid(chunk_width),
+        (tile.1 + chunk_height / 2).div_euclid(chunk_height),
+    )
+}
+
+// Returns the tile at the bottom left corner of the chunk.
+pub fn chunk_to_tile(config: &MapConfig, chunk: (i32, i32)) -> (i32, i32) {
+    let chunk_width = config.chunk_width as i32;
+    let chunk_height = config.chunk_height as i32;
+    (
+        chunk.0 * chunk_width - chunk_width / 2,
+        chunk.1 * chunk_height - chunk_height / 2,
+    )
+}
+
+pub fn world_to_chunk(config: &MapConfig, position: &Vec3) -> (i32, i32) {
+    tile_to_chunk(config, world_to_tile(config, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MapConfig {
+        MapConfig {
+            width: 4,
+            height: 3,
+            tile_size: 32,
+            chunk_width: 2,
+            chunk_height: 2,
+        }
+    }
+
+    #[test]
+    fn world_to_tile_rounds_down() {
+        let config = config();
+        assert_eq!(world_to_tile(&config, &Vec3::new(0.0, 0.0, 0.0)), (0, 0));
+        assert_eq!(world_to_tile(&config, &Vec3::new(31.9, 32.0, 0.0)), (0, 1));
+        assert_eq!(world_to_tile(&config, &Vec3::new(128.0, 96.0, 0.0)), (4, 3));
+    }
+
+    #[test]
+    fn world_to_tile_handles_negative_positions() {
+        let config = config();
+        assert_eq!(world_to_tile(&config, &Vec3::new(-0.5, -32.0, 0.0)), (-1, -1));
+        assert_eq!(world_to_tile(&config, &Vec3::new(-32.5, 0.0, 0.0)), (-2, 0));
+    }
+
+    #[test]
+    fn tile_to_world_is_the_tile_center() {
+        let config = config();
+        assert_eq!(tile_to_world(&config, (1, 2), 5.0), Vec3::new(48.0, 80.0, 5.0));
+        assert_eq!(tile_to_world(&config, (-1, 0), 0.0), Vec3::new(-16.0, 16.0, 0.0));
+    }
+
+    #[test]
+    fn tile_and_world_round_trip() {
+        let config = config();
+        for tile in [(0, 0), (3, 2), (-1, -1), (4, 3), (-5, 7)].iter() {
+            assert_eq!(world_to_tile(&config, &tile_to_world(&config, *tile, 0.0)), *tile);
+        }
+    }
+
+    #[test]
+    fn chunks_are_centered_on_their_origin() {
+        let config = config();
+        assert_eq!(tile_to_chunk(&config, (0, 0)), (0, 0));
+        assert_eq!(tile_to_chunk(&config, (-1, -1)), (0, 0));
+        assert_eq!(tile_to_chunk(&config, (1, 1)), (1, 1));
+        assert_eq!(tile_to_chunk(&config, (-2, 0)), (-1, 0));
+        assert_eq!(chunk_to_tile(&config, (0, 0)), (-1, -1));
+    }
+
+    #[test]
+    fn chunk_to_tile_is_inside_the_chunk() {
+        let config = config();
+        for chunk in [(0, 0), (1, 2), (-1, -3)].iter() {
+            assert_eq!(tile_to_chunk(&config, chunk_to_tile(&config, *chunk)), *chunk);
+        }
+    }
+}
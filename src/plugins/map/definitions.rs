@@ -0,0 +1,264 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::fov::VisibleTiles;
+use super::map_creator::{Map, TileType};
+use super::map_plugin::MapState;
+
+pub const TILE_DEFINITIONS_PATH: &str = "world.tiles.ron";
+pub const BIOME_DEFINITIONS_PATH: &str = "world.biomes.ron";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TileDefinition {
+    pub texture: String,
+    pub walkable: bool,
+    pub block_view: bool,
+    // Cost of entering the tile, 1 being the cheapest terrain.
+    pub movement_cost: u32,
+}
+
+impl TileDefinition {
+    fn new(texture: &str, walkable: bool, block_view: bool, movement_cost: u32) -> Self {
+        TileDefinition {
+            texture: texture.to_string(),
+            walkable,
+            block_view,
+            movement_cost,
+        }
+    }
+}
+
+//
+// Per TileType rules, loaded from world.tiles.ron. The default values are the
+// ones shipped in the asset, they are used until the asset is loaded.
+//
+#[derive(Debug, Clone, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "4b895fb4-35e5-4dd4-b09b-4bc9fc410327"]
+pub struct TileDefinitions {
+    pub tiles: HashMap<TileType, TileDefinition>,
+}
+
+impl Default for TileDefinitions {
+    fn default() -> Self {
+        let mut tiles: HashMap<TileType, TileDefinition> = HashMap::new();
+        tiles.insert(TileType::DeepWater, TileDefinition::new("map_tiles/deep_water.png", false, false, 1));
+        tiles.insert(TileType::Dirt, TileDefinition::new("map_tiles/dirt.png", true, false, 1));
+        tiles.insert(TileType::Grass, TileDefinition::new("map_tiles/grass.png", true, false, 1));
+        tiles.insert(TileType::Forest, TileDefinition::new("map_tiles/forest.png", true, true, 3));
+        tiles.insert(TileType::Rock, TileDefinition::new("map_tiles/rock.png", false, true, 1));
+        tiles.insert(TileType::Sand, TileDefinition::new("map_tiles/sand.png", true, false, 2));
+        tiles.insert(TileType::Savannah, TileDefinition::new("map_tiles/savannah.png", true, false, 1));
+        tiles.insert(TileType::ShallowWater, TileDefinition::new("map_tiles/shallow_water.png", false, false, 1));
+        tiles.insert(TileType::Shore, TileDefinition::new("map_tiles/shore.png", true, false, 2));
+        tiles.insert(TileType::Snow, TileDefinition::new("map_tiles/snow.png", true, false, 2));
+        tiles.insert(TileType::Mountain, TileDefinition::new("map_tiles/mountain.png", false, true, 1));
+
+        TileDefinitions { tiles }
+    }
+}
+
+impl TileDefinitions {
+    pub fn get(&self, tile_type: TileType) -> &TileDefinition {
+        &self.tiles[&tile_type]
+    }
+
+    // Fills the tile types missing from a loaded asset with the default rules.
+    pub fn complete(mut self) -> Self {
+        let defaults = TileDefinitions::default();
+        for tile_type in TileType::ALL.iter() {
+            if !self.tiles.contains_key(tile_type) {
+                warn!("No definition for {} in {}, using the default one.", tile_type, TILE_DEFINITIONS_PATH);
+                self.tiles.insert(*tile_type, defaults.get(*tile_type).clone());
+            }
+        }
+        self
+    }
+}
+
+//
+// Thresholds used to pick the biome of a tile, loaded from world.biomes.ron.
+//
+#[derive(Debug, Clone, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "80edf7a8-1994-4270-bbaf-fe415dd421cd"]
+pub struct BiomeDefinitions {
+    // Elevation thresholds, from the sea floor up to the peaks.
+    pub deep_water_level: f32,
+    pub shallow_water_level: f32,
+    pub shore_level: f32,
+    pub rock_level: f32,
+    pub mountain_level: f32,
+    // Below this temperature, rocky heights are covered with snow.
+    pub snow_line_temperature: f32,
+    // How much the temperature drops at the highest elevation.
+    pub altitude_cooling: f32,
+    // Share of the temperature given by the latitude, the rest comes from noise.
+    pub latitude_weight: f32,
+    // Whittaker style table, indexed by temperature band (cold to hot) then by
+    // moisture band (dry to wet).
+    pub table: Vec<Vec<TileType>>,
+}
+
+impl Default for BiomeDefinitions {
+    fn default() -> Self {
+        BiomeDefinitions {
+            deep_water_level: 0.1,
+            shallow_water_level: 0.2,
+            shore_level: 0.25,
+            rock_level: 0.8,
+            mountain_level: 0.9,
+            snow_line_temperature: 0.3,
+            altitude_cooling: 0.4,
+            latitude_weight: 0.6,
+            table: vec![
+                vec![TileType::Dirt, TileType::Snow, TileType::Snow, TileType::Snow],
+                vec![TileType::Dirt, TileType::Grass, TileType::Forest, TileType::Forest],
+                vec![TileType::Savannah, TileType::Grass, TileType::Grass, TileType::Forest],
+                vec![TileType::Sand, TileType::Savannah, TileType::Forest, TileType::Forest],
+            ],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TileDefinitionsLoader;
+
+impl AssetLoader for TileDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definitions = ron::de::from_bytes::<TileDefinitions>(bytes)?.complete();
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tiles.ron"]
+    }
+}
+
+#[derive(Default)]
+pub struct BiomeDefinitionsLoader;
+
+impl AssetLoader for BiomeDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definitions = ron::de::from_bytes::<BiomeDefinitions>(bytes)?;
+            if definitions.table.is_empty() || definitions.table.iter().any(|row| row.is_empty()) {
+                return Err(anyhow::anyhow!("the biome table of {} has an empty row", BIOME_DEFINITIONS_PATH));
+            }
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["biomes.ron"]
+    }
+}
+
+#[derive(Default)]
+pub struct DefinitionHandles {
+    pub tiles: Handle<TileDefinitions>,
+    pub biomes: Handle<BiomeDefinitions>,
+}
+
+pub fn load_definitions(
+    mut handles: ResMut<DefinitionHandles>,
+    asset_server: Res<AssetServer>,
+) {
+    if let Err(error) = asset_server.watch_for_changes() {
+        warn!("Definitions won't be hot reloaded: {:?}", error);
+    }
+
+    handles.tiles = asset_server.load(TILE_DEFINITIONS_PATH);
+    handles.biomes = asset_server.load(BIOME_DEFINITIONS_PATH);
+}
+
+//
+// System applying the loaded, or reloaded, definitions to the map. A tile
+// change updates the tile rules of the level. A biome change is only used by
+// the next generated level: regenerating the current one would lose what was
+// explored and could leave the player and the monsters on unwalkable tiles.
+// Texture changes are picked up by the atlas on the next start.
+//
+#[allow(clippy::too_many_arguments)]
+pub fn apply_definitions_system(
+    mut tile_events: EventReader<AssetEvent<TileDefinitions>>,
+    mut biome_events: EventReader<AssetEvent<BiomeDefinitions>>,
+    handles: Res<DefinitionHandles>,
+    tile_assets: Res<Assets<TileDefinitions>>,
+    biome_assets: Res<Assets<BiomeDefinitions>>,
+    mut tile_definitions: ResMut<TileDefinitions>,
+    mut biome_definitions: ResMut<BiomeDefinitions>,
+    mut map: ResMut<Map>,
+    mut map_state: ResMut<MapState>,
+    mut visible_tiles: ResMut<VisibleTiles>,
+) {
+    let mut tiles_changed = false;
+    for event in tile_events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != handles.tiles {
+            continue;
+        }
+        if let Some(definitions) = tile_assets.get(handle) {
+            if *definitions != *tile_definitions {
+                *tile_definitions = definitions.clone();
+                tiles_changed = true;
+            }
+        }
+    }
+
+    let mut biomes_changed = false;
+    for event in biome_events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != handles.biomes {
+            continue;
+        }
+        if let Some(definitions) = biome_assets.get(handle) {
+            if *definitions != *biome_definitions {
+                *biome_definitions = definitions.clone();
+                biomes_changed = true;
+            }
+        }
+    }
+
+    // The generation uses the definitions once it's done.
+    if !map.is_generated() {
+        return;
+    }
+
+    if biomes_changed {
+        info!("Biome definitions changed, they apply to the next generated level.");
+    }
+    if !tiles_changed {
+        return;
+    }
+
+    info!("Tile definitions changed, updating the level.");
+    map.apply_tile_definitions(&tile_definitions);
+    visible_tiles.origin = None;
+
+    // Have the tilemap and the collisions rebuilt from the new level.
+    map_state.spawned = false;
+    map_state.collisions.clear();
+}
@@ -0,0 +1,341 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use super::definitions::{BiomeDefinitions, TileDefinitions, BIOME_DEFINITIONS_PATH, TILE_DEFINITIONS_PATH};
+use super::generation::{MapGenerationSettings, MAP_GENERATION_CONFIG_PATH};
+use super::map_config::MapConfig;
+use super::map_creator::{Map, TileType};
+use crate::world_seed::WorldSeed;
+
+pub const EXPORT_MAP_ARGUMENT: &str = "--export-map";
+pub const EXPORT_ELEVATION_ARGUMENT: &str = "--export-elevation";
+
+// Share of its colour kept by an unexplored tile when the explored areas are
+// overlaid.
+const UNEXPLORED_BRIGHTNESS: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportLayer {
+    // Greyscale, black at the sea floor and white at the peaks.
+    Elevation,
+    // One colour per TileType, see biome_color.
+    Biome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub layer: ExportLayer,
+    // Darkens the tiles the player hasn't explored.
+    pub overlay_explored: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            layer: ExportLayer::Biome,
+            overlay_explored: false,
+        }
+    }
+}
+
+pub fn biome_color(tile_type: TileType) -> [u8; 3] {
+    match tile_type {
+        TileType::DeepWater => [16, 42, 110],
+        TileType::ShallowWater => [48, 96, 176],
+        TileType::Shore => [214, 196, 140],
+        TileType::Sand => [236, 214, 150],
+        TileType::Dirt => [134, 100, 64],
+        TileType::Grass => [92, 160, 60],
+        TileType::Savannah => [178, 176, 86],
+        TileType::Forest => [34, 96, 40],
+        TileType::Rock => [120, 116, 110],
+        TileType::Mountain => [84, 78, 74],
+        TileType::Snow => [240, 244, 250],
+    }
+}
+
+//
+// RGB pixels of a map preview. The image is drawn the way the map is shown in
+// game: the tile (0, 0) is at the bottom left, so the rows are flipped.
+//
+pub struct MapImage {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl MapImage {
+    pub fn render(map: &Map, options: ExportOptions) -> Result<MapImage, String> {
+        if !map.is_generated() {
+            return Err("the map is not generated yet".to_string());
+        }
+
+        let mut pixels = Vec::with_capacity(map.width * map.height);
+        for row in 0..map.height {
+            let y = (map.height - 1 - row) as i32;
+            for x in 0..map.width as i32 {
+                let tile_info = map
+                    .get_tileinfo_at(x, y)
+                    .ok_or_else(|| format!("no tile at ({}, {})", x, y))?;
+
+                let mut color = match options.layer {
+                    ExportLayer::Elevation => {
+                        let elevation = map
+                            .elevation_at(x, y)
+                            .ok_or_else(|| "the map has no elevation".to_string())?;
+                        let grey = (elevation.max(0.0).min(1.0) * 255.0).round() as u8;
+                        [grey, grey, grey]
+                    }
+                    ExportLayer::Biome => biome_color(tile_info.tile_type),
+                };
+
+                if options.overlay_explored && !tile_info.explored {
+                    for channel in color.iter_mut() {
+                        *channel = (*channel as f32 * UNEXPLORED_BRIGHTNESS).round() as u8;
+                    }
+                }
+                pixels.push(color);
+            }
+        }
+
+        Ok(MapImage {
+            width: map.width,
+            height: map.height,
+            pixels,
+        })
+    }
+
+    // Colour of the pixel, the row 0 being the top of the image.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.pixels[y * self.width + x])
+    }
+
+    // Saves as a PNG or a BMP, depending on the extension of path.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("png") => self.save_png(path),
+            Some("bmp") => self.save_bmp(path),
+            _ => Err(format!("unsupported image format for {}, use .png or .bmp", path)),
+        }
+    }
+
+    fn save_png(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|error| error.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self.pixels.iter().flat_map(|pixel| pixel.iter().copied()).collect();
+        let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+        writer.write_image_data(&data).map_err(|error| error.to_string())
+    }
+
+    fn save_bmp(&self, path: &str) -> Result<(), String> {
+        let mut image = bmp::Image::new(self.width as u32, self.height as u32);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let [red, green, blue] = self.pixels[y * self.width + x];
+                image.set_pixel(x as u32, y as u32, bmp::Pixel::new(red, green, blue));
+            }
+        }
+        image.save(path).map_err(|error| error.to_string())
+    }
+}
+
+// Value of a command line argument, as in `--export-map map.png` or
+// `--export-map=map.png`.
+fn argument_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(prefix.as_str()).map(|value| value.to_string())
+        }
+    })
+}
+
+//
+// Command line export, without opening the game: `--export-map map.png`
+// writes the biomes of the world given by --seed, `--export-elevation map.png`
+// its elevation. The definitions come from assets/world.tiles.ron and
+// assets/world.biomes.ron, like in game. Returns None
+// when neither argument is given, otherwise the path written or the error.
+//
+pub fn export_from_args() -> Option<Result<String, String>> {
+    let args: Vec<String> = env::args().collect();
+    let (path, layer) = match argument_value(&args, EXPORT_MAP_ARGUMENT) {
+        Some(path) => (path, ExportLayer::Biome),
+        None => (argument_value(&args, EXPORT_ELEVATION_ARGUMENT)?, ExportLayer::Elevation),
+    };
+
+    let tiles = fs::read_to_string(Path::new("assets").join(TILE_DEFINITIONS_PATH))
+        .ok()
+        .and_then(|content| ron::de::from_str::<TileDefinitions>(&content).ok())
+        .map(TileDefinitions::complete)
+        .unwrap_or_default();
+    let biomes = fs::read_to_string(Path::new("assets").join(BIOME_DEFINITIONS_PATH))
+        .ok()
+        .and_then(|content| ron::de::from_str::<BiomeDefinitions>(&content).ok())
+        .filter(|biomes| !biomes.table.is_empty() && biomes.table.iter().all(|row| !row.is_empty()))
+        .unwrap_or_default();
+
+    let mut map = MapGenerationSettings::load(MAP_GENERATION_CONFIG_PATH)
+        .map_builder(WorldSeed::from_env(), &MapConfig::default())
+        .build();
+    map.generate(&tiles, &biomes, &mut |_| {});
+
+    let options = ExportOptions {
+        layer,
+        overlay_explored: false,
+    };
+    Some(map.save_image(&path, options).map(|_| path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::map::map_creator::MapBuilder;
+    use bevy::utils::HashSet;
+
+    const WIDTH: usize = 12;
+    const HEIGHT: usize = 9;
+
+    // Generated with the default settings and definitions.
+    fn seeded_map() -> Map {
+        let config = MapConfig {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            ..Default::default()
+        };
+        let mut map = MapGenerationSettings::default()
+            .map_builder(WorldSeed(21), &config)
+            .build();
+        map.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        map
+    }
+
+    fn render(map: &Map, layer: ExportLayer, overlay_explored: bool) -> MapImage {
+        MapImage::render(map, ExportOptions { layer, overlay_explored }).unwrap()
+    }
+
+    fn tile_type(map: &Map, x: usize, y: usize) -> TileType {
+        map.get_tileinfo_at(x as i32, y as i32).unwrap().tile_type
+    }
+
+    #[test]
+    fn every_tile_type_has_its_own_color() {
+        let colors: HashSet<[u8; 3]> = TileType::ALL.iter().map(|tile_type| biome_color(*tile_type)).collect();
+        assert_eq!(colors.len(), TileType::ALL.len());
+    }
+
+    #[test]
+    fn biome_pixels_are_the_tile_colors_with_the_rows_flipped() {
+        let map = seeded_map();
+        let image = render(&map, ExportLayer::Biome, false);
+        assert_eq!((image.width, image.height), (WIDTH, HEIGHT));
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                assert_eq!(image.pixel(x, HEIGHT - 1 - y), Some(biome_color(tile_type(&map, x, y))));
+            }
+        }
+    }
+
+    #[test]
+    fn the_corners_and_the_last_row_and_column_are_drawn() {
+        let image = render(&seeded_map(), ExportLayer::Biome, false);
+
+        // Known biomes of the seed 21, away from the thresholds between two
+        // biomes. The sea is at the top left of the image, the tile (0, 8).
+        assert_eq!(image.pixel(0, 0), Some([16, 42, 110]));
+        // Mountains at the bottom right, the tile (11, 0).
+        assert_eq!(image.pixel(WIDTH - 1, HEIGHT - 1), Some([84, 78, 74]));
+        // Dirt on the tile (6, 6), snow on the tile (5, 1).
+        assert_eq!(image.pixel(6, 2), Some([134, 100, 64]));
+        assert_eq!(image.pixel(5, 7), Some([240, 244, 250]));
+
+        assert_eq!(image.pixel(WIDTH, 0), None);
+        assert_eq!(image.pixel(0, HEIGHT), None);
+    }
+
+    #[test]
+    fn elevation_is_greyscale() {
+        let map = seeded_map();
+        let image = render(&map, ExportLayer::Elevation, false);
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let elevation = map.elevation_at(x as i32, y as i32).unwrap();
+                let grey = (elevation.max(0.0).min(1.0) * 255.0).round() as u8;
+                assert_eq!(image.pixel(x, HEIGHT - 1 - y), Some([grey, grey, grey]));
+            }
+        }
+    }
+
+    #[test]
+    fn unexplored_tiles_are_darkened() {
+        let mut map = seeded_map();
+        map.get_tileinfo_mut(2, 3).unwrap().explored = true;
+        let image = render(&map, ExportLayer::Biome, true);
+
+        assert_eq!(image.pixel(2, HEIGHT - 1 - 3), Some(biome_color(tile_type(&map, 2, 3))));
+        let [red, green, blue] = biome_color(tile_type(&map, 5, 1));
+        let darken = |channel: u8| (channel as f32 * UNEXPLORED_BRIGHTNESS).round() as u8;
+        assert_eq!(image.pixel(5, HEIGHT - 1 - 1), Some([darken(red), darken(green), darken(blue)]));
+    }
+
+    #[test]
+    fn ungenerated_maps_cant_be_rendered() {
+        let map = MapBuilder::new().with_size(WIDTH, HEIGHT).build();
+        assert!(MapImage::render(&map, ExportOptions::default()).is_err());
+    }
+
+    #[test]
+    fn saved_pngs_hold_the_pixels() {
+        let image = render(&seeded_map(), ExportLayer::Biome, false);
+        let path = env::temp_dir().join(format!("bevy_tuto_export_{}.png", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        image.save(&path).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width as usize, info.height as usize), (WIDTH, HEIGHT));
+        let last = (HEIGHT * WIDTH - 1) * 3;
+        assert_eq!(image.pixel(WIDTH - 1, HEIGHT - 1), Some([data[last], data[last + 1], data[last + 2]]));
+        assert_eq!(image.pixel(0, 0), Some([data[0], data[1], data[2]]));
+    }
+
+    #[test]
+    fn only_png_and_bmp_are_saved() {
+        let image = render(&seeded_map(), ExportLayer::Biome, false);
+        assert!(image.save("map.jpg").is_err());
+    }
+
+    #[test]
+    fn export_arguments_take_a_path() {
+        let args: Vec<String> = ["game", "--seed", "3", "--export-map", "map.png"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(argument_value(&args, EXPORT_MAP_ARGUMENT), Some("map.png".to_string()));
+        assert_eq!(argument_value(&args, EXPORT_ELEVATION_ARGUMENT), None);
+
+        let args = vec!["game".to_string(), "--export-elevation=height.bmp".to_string()];
+        assert_eq!(argument_value(&args, EXPORT_ELEVATION_ARGUMENT), Some("height.bmp".to_string()));
+    }
+}
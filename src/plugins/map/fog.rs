@@ -0,0 +1,132 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_tilemap::prelude::*;
+
+use super::fov::VisibleTiles;
+use super::map_creator::Map;
+use super::tile_atlas::TileAtlas;
+
+// Tilemap layer drawn on top of the terrain layer.
+pub const FOG_SPRITE_ORDER: usize = 1;
+
+const UNEXPLORED_TINT: Color = Color::BLACK;
+const EXPLORED_TINT: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+const VISIBLE_TINT: Color = Color::NONE;
+
+#[derive(Default)]
+pub struct FogOfWar {
+    pub covered: bool,
+    // Tiles that were visible the last time the fog layer was updated.
+    pub shown: HashSet<(i32, i32)>,
+}
+
+pub fn fog_tint(map: &Map, visible_tiles: &VisibleTiles, x: i32, y: i32) -> Color {
+    if visible_tiles.contains(x, y) {
+        VISIBLE_TINT
+    } else {
+        match map.get_tileinfo_at(x, y) {
+            Some(tile_info) if tile_info.explored => EXPLORED_TINT,
+            _ => UNEXPLORED_TINT,
+        }
+    }
+}
+
+//
+// Fog tiles over the whole map, with the same tints as the updates so the
+// tiles explored in a loaded save start out remembered.
+//
+pub fn cover_tiles(map: &Map, visible_tiles: &VisibleTiles, sprite_index: usize) -> Vec<Tile> {
+    let mut tiles = Vec::with_capacity(map.width * map.height);
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            tiles.push(Tile {
+                point: (x, y),
+                sprite_order: FOG_SPRITE_ORDER,
+                sprite_index,
+                tint: fog_tint(map, visible_tiles, x, y),
+            });
+        }
+    }
+    tiles
+}
+
+//
+// System keeping the fog of war layer in sync with the player's field of view.
+//
+pub fn fog_of_war_system(
+    map: Res<Map>,
+    visible_tiles: Res<VisibleTiles>,
+    tile_atlas: Res<TileAtlas>,
+    mut fog: ResMut<FogOfWar>,
+    mut query: Query<&mut Tilemap>,
+) {
+    let sprite_index = match tile_atlas.fog_index {
+        Some(sprite_index) => sprite_index,
+        None => return,
+    };
+
+    for mut tilemap in query.iter_mut() {
+        let mut tiles = Vec::new();
+
+        if !fog.covered {
+            tiles = cover_tiles(&map, &visible_tiles, sprite_index);
+            fog.covered = true;
+            // The cover already holds the current view.
+            fog.shown = visible_tiles.tiles.clone();
+        } else if !visible_tiles.is_changed() {
+            continue;
+        }
+
+        // Only the tiles entering or leaving the view changed state, the
+        // tilemap then only rebuilds the chunks holding them.
+        let changed: Vec<(i32, i32)> = fog
+            .shown
+            .symmetric_difference(&visible_tiles.tiles)
+            .cloned()
+            .collect();
+
+        for (x, y) in changed {
+            tiles.push(Tile {
+                point: (x, y),
+                sprite_order: FOG_SPRITE_ORDER,
+                sprite_index,
+                tint: fog_tint(&map, &visible_tiles, x, y),
+            });
+        }
+
+        if tiles.is_empty() {
+            continue;
+        }
+
+        if let Err(error) = tilemap.insert_tiles(tiles) {
+            error!("Unable to update the fog of war: {:?}", error);
+            continue;
+        }
+
+        fog.shown = visible_tiles.tiles.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tint_at(tiles: &[Tile], point: (i32, i32)) -> Color {
+        tiles.iter().find(|tile| tile.point == point).unwrap().tint
+    }
+
+    #[test]
+    fn explored_tiles_start_remembered() {
+        let mut map = Map::from_rows(&["...", "...", "..."]);
+        map.get_tileinfo_mut(2, 2).unwrap().explored = true;
+        map.get_tileinfo_mut(1, 1).unwrap().explored = true;
+        let mut visible_tiles = VisibleTiles::default();
+        visible_tiles.tiles.insert((1, 1));
+
+        let tiles = cover_tiles(&map, &visible_tiles, 0);
+        assert_eq!(tiles.len(), 9);
+        assert_eq!(tint_at(&tiles, (2, 2)), EXPLORED_TINT);
+        assert_eq!(tint_at(&tiles, (1, 1)), VISIBLE_TINT);
+        assert_eq!(tint_at(&tiles, (0, 0)), UNEXPLORED_TINT);
+    }
+}
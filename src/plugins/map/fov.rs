@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use super::coordinates::world_to_tile;
+use super::map_config::MapConfig;
+use super::map_creator::Map;
+use crate::player::Player;
+
+pub const FOV_RADIUS: i32 = 8;
+
+// Multipliers used to transform the coordinates of the first octant into the
+// seven other ones: (xx, xy, yx, yy).
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+//
+// Tiles currently seen by the player, refreshed every time the player moves.
+//
+#[derive(Default)]
+pub struct VisibleTiles {
+    pub tiles: HashSet<(i32, i32)>,
+    pub origin: Option<(i32, i32)>,
+}
+
+impl VisibleTiles {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.tiles.contains(&(x, y))
+    }
+}
+
+//
+// Recursive shadowcasting, see
+// http://www.roguebasin.com/index.php?title=FOV_using_recursive_shadowcasting
+//
+pub fn compute_fov(map: &Map, origin: (i32, i32), radius: i32) -> HashSet<(i32, i32)> {
+    let mut visible = HashSet::default();
+
+    if !map.in_bounds(origin.0, origin.1) {
+        return visible;
+    }
+
+    visible.insert(origin);
+    for octant in OCTANTS.iter() {
+        cast_light(map, origin, radius, 1, 1.0, 0.0, *octant, &mut visible);
+    }
+
+    visible
+}
+
+// Tiles outside of the map block the view so the light never leaks out.
+fn blocks_sight(map: &Map, x: i32, y: i32) -> bool {
+    match map.get_tileinfo_at(x, y) {
+        Some(tile_info) => tile_info.block_view,
+        None => true,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn cast_light(
+    map: &Map,
+    origin: (i32, i32),
+    radius: i32,
+    row: i32,
+    mut start: f32,
+    end: f32,
+    octant: (i32, i32, i32, i32),
+    visible: &mut HashSet<(i32, i32)>,
+) {
+    if start < end {
+        return;
+    }
+
+    let (xx, xy, yx, yy) = octant;
+    let radius_squared = radius * radius;
+    let mut new_start = 0.0;
+
+    for j in row..=radius {
+        let dy = -j;
+        let mut blocked = false;
+
+        for dx in -j..=0 {
+            let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+
+            if start < right_slope {
+                continue;
+            } else if end > left_slope {
+                break;
+            }
+
+            let x = origin.0 + dx * xx + dy * xy;
+            let y = origin.1 + dx * yx + dy * yy;
+
+            if dx * dx + dy * dy <= radius_squared && map.in_bounds(x, y) {
+                visible.insert((x, y));
+            }
+
+            let opaque = blocks_sight(map, x, y);
+            if blocked {
+                if opaque {
+                    new_start = right_slope;
+                } else {
+                    blocked = false;
+                    start = new_start;
+                }
+            } else if opaque && j < radius {
+                // This tile starts a shadow, scan the next row up to it.
+                blocked = true;
+                cast_light(map, origin, radius, j + 1, start, left_slope, octant, visible);
+                new_start = right_slope;
+            }
+        }
+
+        if blocked {
+            break;
+        }
+    }
+}
+
+//
+// System recomputing the player's field of view when the player moves or
+// when the level was regenerated.
+//
+pub fn fov_system(
+    config: Res<MapConfig>,
+    mut map: ResMut<Map>,
+    mut visible_tiles: ResMut<VisibleTiles>,
+    query: Query<&Transform, With<Player>>,
+) {
+    if !map.is_generated() {
+        return;
+    }
+
+    for transform in query.iter() {
+        let origin = world_to_tile(&config, &transform.translation);
+        if visible_tiles.origin == Some(origin) {
+            continue;
+        }
+
+        visible_tiles.tiles = compute_fov(&map, origin, FOV_RADIUS);
+        visible_tiles.origin = Some(origin);
+
+        for (x, y) in visible_tiles.tiles.iter() {
+            if let Some(tile_info) = map.get_tileinfo_mut(*x, *y) {
+                tile_info.explored = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Open 7x5 map with a single tile at (2, 2), on the row of the origin.
+    fn map_with(tile: char) -> Map {
+        let row = format!("..{}....", tile);
+        Map::from_rows(&[".......", ".......", &row, ".......", "......."])
+    }
+
+    #[test]
+    fn walls_cast_a_shadow() {
+        let visible = compute_fov(&map_with('#'), (0, 2), FOV_RADIUS);
+        assert!(visible.contains(&(1, 2)));
+        // The wall itself is seen, not what's behind it.
+        assert!(visible.contains(&(2, 2)));
+        assert!(!visible.contains(&(3, 2)));
+        assert!(!visible.contains(&(4, 2)));
+        // Tiles away from the shadow are still seen.
+        assert!(visible.contains(&(4, 4)));
+        assert!(visible.contains(&(4, 0)));
+    }
+
+    #[test]
+    fn forests_rocks_and_mountains_block_the_view() {
+        for tile in ['f', '#', 'm'].iter() {
+            let visible = compute_fov(&map_with(*tile), (0, 2), FOV_RADIUS);
+            assert!(visible.contains(&(2, 2)), "{}", tile);
+            assert!(!visible.contains(&(3, 2)), "{}", tile);
+        }
+    }
+
+    #[test]
+    fn open_terrain_doesnt_block_the_view() {
+        for tile in ['.', 'd', 's', 'w', '~'].iter() {
+            let visible = compute_fov(&map_with(*tile), (0, 2), FOV_RADIUS);
+            assert!(visible.contains(&(3, 2)), "{}", tile);
+            assert!(visible.contains(&(6, 2)), "{}", tile);
+        }
+    }
+
+    #[test]
+    fn the_view_stops_at_the_radius() {
+        let row = ".........";
+        let map = Map::from_rows(&[row, row, row, row, row, row, row, row, row]);
+        let visible = compute_fov(&map, (4, 4), 3);
+        assert!(visible.contains(&(7, 4)));
+        assert!(visible.contains(&(4, 1)));
+        assert!(!visible.contains(&(8, 4)));
+        assert!(!visible.contains(&(4, 0)));
+        // Circular radius: 2² + 2² <= 3², 3² + 1² > 3².
+        assert!(visible.contains(&(6, 6)));
+        assert!(!visible.contains(&(7, 5)));
+    }
+
+    #[test]
+    fn corners_only_see_inside_the_map() {
+        let map = Map::from_rows(&["...", "...", "..."]);
+        for corner in [(0, 0), (2, 0), (0, 2), (2, 2)].iter() {
+            let visible = compute_fov(&map, *corner, FOV_RADIUS);
+            assert_eq!(visible.len(), 9, "{:?}", corner);
+            assert!(visible.iter().all(|(x, y)| map.in_bounds(*x, *y)));
+        }
+    }
+
+    #[test]
+    fn nothing_is_seen_from_outside_the_map() {
+        let map = Map::from_rows(&["...", "...", "..."]);
+        assert!(compute_fov(&map, (-1, 0), FOV_RADIUS).is_empty());
+        assert!(compute_fov(&map, (3, 3), FOV_RADIUS).is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use serde::Deserialize;
+use std::any::Any;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use super::definitions::{BiomeDefinitions, TileDefinitions};
+use super::map_config::MapConfig;
+use super::map_creator::{Map, MapBuilder};
+use crate::save::LoadedGame;
+use crate::state::AppState;
+use crate::world_seed::WorldSeed;
+
+pub const MAP_GENERATION_CONFIG_PATH: &str = "config/map_generation.ron";
+
+//
+// Noise parameters of the terrain, loaded from config/map_generation.ron.
+// The file is read again each time a world is generated, so an edit applies
+// to the next run without restarting the game.
+//
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct MapGenerationSettings {
+    pub frequency: f32,
+    pub gain: f32,
+    pub lacunarity: f32,
+    pub octaves: u8,
+}
+
+impl Default for MapGenerationSettings {
+    fn default() -> Self {
+        MapGenerationSettings {
+            frequency: 0.03,
+            gain: 2.5,
+            lacunarity: 0.55,
+            octaves: 2,
+        }
+    }
+}
+
+impl MapGenerationSettings {
+    pub fn load(path: &str) -> MapGenerationSettings {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                info!("No map generation settings at {}, using the defaults.", path);
+                return MapGenerationSettings::default();
+            }
+        };
+
+        match ron::de::from_str(&content) {
+            Ok(settings) => settings,
+            Err(error) => {
+                warn!("Invalid map generation settings {}: {}", path, error);
+                MapGenerationSettings::default()
+            }
+        }
+    }
+
+    pub fn map_builder(&self, seed: WorldSeed, config: &MapConfig) -> MapBuilder {
+        MapBuilder::new()
+            .with_seed(seed)
+            .with_frequency(self.frequency)
+            .with_gain(self.gain)
+            .with_lacunarity(self.lacunarity)
+            .with_octaves(self.octaves)
+            .with_size(config.width as usize, config.height as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MapGenerationProgress {
+    // From 0 to 1.
+    pub fraction: f32,
+    pub done: bool,
+}
+
+// The generated map, with the definitions it was generated with.
+struct GeneratedMap {
+    map: Map,
+    tiles: TileDefinitions,
+    biomes: BiomeDefinitions,
+    // Brought back from a saved game, its level is the saved one.
+    restored: bool,
+}
+
+//
+// The map being generated, present while the task runs. A panic of the
+// generation is caught by the task and given back as an error.
+//
+pub struct MapGenerationTask {
+    task: Task<Result<GeneratedMap, String>>,
+    // Bits of the f32 fraction done, there is no atomic float.
+    progress: Arc<AtomicU32>,
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown error".to_string(),
+        },
+    }
+}
+
+//
+// Starts generating the map on the async compute pool. The map of a saved
+// game is generated again from its own parameters, then gets its saved tiles
+// back.
+//
+#[allow(clippy::too_many_arguments)]
+pub fn start_map_generation_system(
+    mut commands: Commands,
+    task_pool: Res<AsyncComputeTaskPool>,
+    config: Res<MapConfig>,
+    seed: Res<WorldSeed>,
+    mut settings: ResMut<MapGenerationSettings>,
+    tiles: Res<TileDefinitions>,
+    biomes: Res<BiomeDefinitions>,
+    loaded_game: Option<ResMut<LoadedGame>>,
+    mut progress: ResMut<MapGenerationProgress>,
+) {
+    info!("World seed is {}.", seed.0);
+    *progress = MapGenerationProgress::default();
+
+    let saved_map = loaded_game.and_then(|mut loaded_game| loaded_game.map.take());
+    let restored = saved_map.is_some();
+    let mut map = match saved_map {
+        Some(saved_map) => saved_map,
+        None => {
+            *settings = MapGenerationSettings::load(MAP_GENERATION_CONFIG_PATH);
+            settings.map_builder(*seed, &config).build()
+        }
+    };
+    let tiles = (*tiles).clone();
+    let biomes = (*biomes).clone();
+    let fraction = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+    let task_fraction = fraction.clone();
+    let task = task_pool.spawn(async move {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut report = |done: f32| task_fraction.store(done.to_bits(), Ordering::Relaxed);
+            if restored {
+                map.regenerate_saved(&tiles, &biomes, &mut report);
+            } else {
+                map.generate(&tiles, &biomes, &mut report);
+            }
+        }))
+        .map_err(panic_message)?;
+
+        Ok(GeneratedMap {
+            map,
+            tiles,
+            biomes,
+            restored,
+        })
+    });
+
+    commands.insert_resource(MapGenerationTask {
+        task,
+        progress: fraction,
+    });
+}
+
+//
+// Reports the progress of the task and replaces the map once it's done. A
+// failed generation goes back to the main menu instead of waiting forever.
+//
+pub fn finish_map_generation_system(
+    mut commands: Commands,
+    task: Option<ResMut<MapGenerationTask>>,
+    tiles: Res<TileDefinitions>,
+    biomes: Res<BiomeDefinitions>,
+    mut map: ResMut<Map>,
+    mut progress: ResMut<MapGenerationProgress>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let mut task = match task {
+        Some(task) => task,
+        None => return,
+    };
+
+    progress.fraction = f32::from_bits(task.progress.load(Ordering::Relaxed));
+
+    let generated = match future::block_on(future::poll_once(&mut task.task)) {
+        Some(generated) => generated,
+        None => return,
+    };
+    commands.remove_resource::<MapGenerationTask>();
+
+    let generated = match generated {
+        Ok(generated) => generated,
+        Err(error) => {
+            error!("World generation failed: {}", error);
+            let _ = state.set(AppState::MainMenu);
+            return;
+        }
+    };
+    *map = generated.map;
+
+    // The definitions were loaded while the task was running. A restored map
+    // keeps its saved level whatever the biomes.
+    if *biomes != generated.biomes && !generated.restored {
+        map.generate_level(&tiles, &biomes);
+    } else if *tiles != generated.tiles {
+        map.apply_tile_definitions(&tiles);
+    }
+
+    info!("World generated.");
+    *progress = MapGenerationProgress {
+        fraction: 1.0,
+        done: true,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    fn app(seed: WorldSeed) -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .add_state(AppState::Generating)
+            .insert_resource(MapConfig::default())
+            .insert_resource(seed)
+            .init_resource::<MapGenerationSettings>()
+            .init_resource::<TileDefinitions>()
+            .init_resource::<BiomeDefinitions>()
+            .init_resource::<MapGenerationProgress>()
+            .init_resource::<Map>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating).with_system(start_map_generation_system.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Generating).with_system(finish_map_generation_system.system()),
+            );
+        app
+    }
+
+    // Updates the app until the task is done.
+    fn generate(app: &mut AppBuilder) {
+        for _ in 0..1000 {
+            app.app.update();
+            if app.app.world.get_resource::<MapGenerationProgress>().unwrap().done {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(app.app.world.get_resource::<MapGenerationProgress>().unwrap().done);
+        assert!(app.app.world.get_resource::<MapGenerationTask>().is_none());
+    }
+
+    #[test]
+    fn the_map_is_generated_in_the_background() {
+        let seed = WorldSeed(9);
+        let mut app = app(seed);
+        generate(&mut app);
+
+        let mut expected = MapGenerationSettings::load(MAP_GENERATION_CONFIG_PATH)
+            .map_builder(seed, &MapConfig::default())
+            .build();
+        expected.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        assert!(*app.app.world.get_resource::<Map>().unwrap() == expected);
+    }
+
+    #[test]
+    fn saved_maps_are_restored_in_the_background() {
+        let seed = WorldSeed(5);
+        let mut saved = MapGenerationSettings::default()
+            .map_builder(seed, &MapConfig::default())
+            .build();
+        saved.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        saved.get_tileinfo_mut(3, 4).unwrap().explored = true;
+        // A saved game holds the noise parameters and the level, not the noise.
+        let content = ron::ser::to_string(&saved).unwrap();
+        let loaded: Map = ron::de::from_str(&content).unwrap();
+
+        let mut app = app(seed);
+        app.insert_resource(LoadedGame {
+            ticks: 0,
+            map: Some(loaded),
+            player: None,
+            monsters: None,
+        });
+        generate(&mut app);
+
+        let map = app.app.world.get_resource::<Map>().unwrap();
+        assert!(map.get_tileinfo_at(3, 4).unwrap().explored);
+        assert!(*map == saved);
+        assert!(app.app.world.get_resource::<LoadedGame>().unwrap().map.is_none());
+    }
+}
@@ -0,0 +1,47 @@
+//
+// Dimensions shared by the map generation, the tilemap and the player
+// movement. Insert it before adding the MapPlugin to override the defaults.
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapConfig {
+    // Map size, in tiles.
+    pub width: u32,
+    pub height: u32,
+    // Size of a tile side, in pixels.
+    pub tile_size: u32,
+    // Size of a tilemap chunk, in tiles.
+    pub chunk_width: u32,
+    pub chunk_height: u32,
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        MapConfig {
+            width: 64,
+            height: 48,
+            tile_size: 32,
+            chunk_width: 16,
+            chunk_height: 16,
+        }
+    }
+}
+
+impl MapConfig {
+    // Map size, in pixels.
+    pub fn world_width(&self) -> f32 {
+        self.width as f32 * self.tile_size as f32
+    }
+
+    pub fn world_height(&self) -> f32 {
+        self.height as f32 * self.tile_size as f32
+    }
+
+    // Number of chunks needed to hold the whole map. The tilemap is centered
+    // on the tile (0, 0) so it has to span twice the map size.
+    pub fn tilemap_dimensions(&self) -> (u32, u32) {
+        (
+            2 * ((self.width + self.chunk_width - 1) / self.chunk_width) + 1,
+            2 * ((self.height + self.chunk_height - 1) / self.chunk_height) + 1,
+        )
+    }
+}
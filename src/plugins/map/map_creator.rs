@@ -1,24 +1,19 @@
-use bevy::{
-    prelude::*,
-    asset::{LoadState},
-    sprite::TextureAtlasBuilder
-};
+use bevy::prelude::*;
 use bevy_tilemap::prelude::*;
-use bmp::Image;
 use rand::Rng;
 use simdnoise::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use super::map_plugin::{MapState, TileSpriteHandles};
-// use crate::player::CursorState;
-
-pub const TILE_SIZE: u32 = 32;
-const MAP_SIZE_X: u32 = 32 * 2;
-const MAP_SIZE_Y: u32 = 24 * 2;
-const SCREEN_MAP_SIZE_X_MAX: u32 = 32;
-const SCREEN_MAP_SIZE_Y_MAX: u32 = 24;
-
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+use super::biome;
+use super::definitions::{BiomeDefinitions, TileDefinitions};
+use super::export::{ExportOptions, MapImage};
+use super::generation::MapGenerationSettings;
+use super::map_config::MapConfig;
+use super::map_plugin::MapState;
+use super::tile_atlas::TileAtlas;
+use crate::world_seed::{WorldSeed, MAP_GENERATION_STREAM};
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum TileType {
     DeepWater,
     Dirt,
@@ -33,6 +28,22 @@ pub enum TileType {
     Mountain,
 }
 
+impl TileType {
+    pub const ALL: [TileType; 11] = [
+        TileType::DeepWater,
+        TileType::Dirt,
+        TileType::Grass,
+        TileType::Forest,
+        TileType::Rock,
+        TileType::Sand,
+        TileType::Savannah,
+        TileType::ShallowWater,
+        TileType::Shore,
+        TileType::Snow,
+        TileType::Mountain,
+    ];
+}
+
 impl fmt::Display for TileType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -51,39 +62,7 @@ impl fmt::Display for TileType {
     }
 }
 
-//#[derive(Copy, Clone)]
-pub struct TileData {
-    pub tile_data: HashMap<TileType, String>,
-}
-
-impl Default for TileData {
-    fn default() -> Self {
-        let mut tile_data_map: HashMap<TileType, String> = HashMap::new();
-        tile_data_map.insert(TileType::DeepWater, "map_tiles/deep_water.png".to_string());
-        tile_data_map.insert(TileType::Dirt, "map_tiles/dirt.png".to_string());
-        tile_data_map.insert(TileType::Grass, "map_tiles/grass.png".to_string());
-        tile_data_map.insert(TileType::Forest, "map_tiles/forest.png".to_string());
-        tile_data_map.insert(TileType::Rock, "map_tiles/rock.png".to_string());
-        tile_data_map.insert(TileType::Sand, "map_tiles/sand.png".to_string());
-        tile_data_map.insert(TileType::Savannah, "map_tiles/savannah.png".to_string());
-        tile_data_map.insert(TileType::ShallowWater, "map_tiles/shallow_water.png".to_string());
-        tile_data_map.insert(TileType::Shore, "map_tiles/shore.png".to_string());
-        tile_data_map.insert(TileType::Snow, "map_tiles/snow.png".to_string());
-        tile_data_map.insert(TileType::Mountain, "map_tiles/mountain.png".to_string());
-
-        TileData {
-            tile_data: tile_data_map,
-        }
-    }
-}
-
-impl TileData {
-    fn get_path(&self, tile_type: TileType) -> String {
-        self.tile_data[&tile_type].clone()
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TileInfo {
     pub x: usize,
     pub y: usize,
@@ -91,56 +70,74 @@ pub struct TileInfo {
     pub explored: bool,
     pub block_view: bool,
     pub walkable: bool,
+    pub movement_cost: u32,
 }
 
 impl TileInfo {
-    pub fn new(x: usize, y: usize, tile_type: TileType) -> TileInfo {
+    pub fn new(x: usize, y: usize, tile_type: TileType, definitions: &TileDefinitions) -> TileInfo {
+        let definition = definitions.get(tile_type);
         TileInfo {
             x: x,
             y: y,
             tile_type: tile_type,
             explored: false,
-            block_view: false,
-            walkable: match tile_type {
-                TileType::DeepWater
-                | TileType::Rock
-                | TileType::ShallowWater
-                | TileType::Mountain => false,
-                TileType::Dirt
-                | TileType::Grass
-                | TileType::Forest
-                | TileType::Sand
-                | TileType::Savannah
-                | TileType::Shore
-                | TileType::Snow => true,
-            },
+            block_view: definition.block_view,
+            walkable: definition.walkable,
+            movement_cost: definition.movement_cost,
         }
     }
+
+    // Updates the rules of the tile, keeping its explored state.
+    pub fn apply_definitions(&mut self, definitions: &TileDefinitions) {
+        let definition = definitions.get(self.tile_type);
+        self.block_view = definition.block_view;
+        self.walkable = definition.walkable;
+        self.movement_cost = definition.movement_cost;
+    }
 }
 
 pub struct MapBuilder {
+    world_seed: u64,
     seed: i32,
+    moisture_seed: i32,
+    temperature_seed: i32,
     frequency: f32,
     lacunarity: f32,
     gain: f32,
     octaves: u8,
-    map_size: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Default for MapBuilder {
+    fn default() -> Self {
+        MapBuilder::new()
+    }
 }
 
 impl MapBuilder {
     pub fn new() -> MapBuilder {
         MapBuilder {
+            world_seed: 0,
             seed: 0,
+            moisture_seed: 0,
+            temperature_seed: 0,
             frequency: 0.0,
             lacunarity: 0.0,
             gain: 0.0,
             octaves: 0,
-            map_size: 0,
+            width: 0,
+            height: 0,
         }
     }
 
-    pub fn with_seed(mut self, seed: i32) -> MapBuilder {
-        self.seed = seed;
+    // The noise seeds are derived from the world seed.
+    pub fn with_seed(mut self, world_seed: WorldSeed) -> MapBuilder {
+        let mut rng = world_seed.rng(MAP_GENERATION_STREAM);
+        self.world_seed = world_seed.0;
+        self.seed = rng.gen();
+        self.moisture_seed = rng.gen();
+        self.temperature_seed = rng.gen();
         self
     }
 
@@ -164,196 +161,321 @@ impl MapBuilder {
         self
     }
 
-    pub fn with_size(mut self, size: usize) -> MapBuilder {
-        self.map_size = size;
+    pub fn with_size(mut self, width: usize, height: usize) -> MapBuilder {
+        self.width = width;
+        self.height = height;
         self
     }
 
     pub fn build(&self) -> Map {
         Map {
             noise_vector: Vec::new(),
+            moisture_vector: Vec::new(),
+            temperature_vector: Vec::new(),
+            world_seed: self.world_seed,
             noise_seed: self.seed,
+            moisture_seed: self.moisture_seed,
+            temperature_seed: self.temperature_seed,
             noise_frequency: self.frequency,
             noise_lacunarity: self.lacunarity,
             noise_gain: self.gain,
             noise_octaves: self.octaves,
-            map_size: self.map_size,
+            width: self.width,
+            height: self.height,
             level_data: Vec::new(),
         }
     }
 }
 
+//
+// Saved games keep the seeds, the noise parameters and the level. The noise
+// itself is generated again on load.
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Map {
+    #[serde(skip)]
     noise_vector: Vec<f32>,
+    #[serde(skip)]
+    moisture_vector: Vec<f32>,
+    #[serde(skip)]
+    temperature_vector: Vec<f32>,
+    pub world_seed: u64,
     noise_seed: i32,
+    moisture_seed: i32,
+    temperature_seed: i32,
     noise_frequency: f32,
     noise_lacunarity: f32,
     noise_gain: f32,
     noise_octaves: u8,
-    pub map_size: usize,
+    pub width: usize,
+    pub height: usize,
     level_data: Vec<TileInfo>,
 }
 
-impl Default for Map {
-    fn default() -> Self {
-        let mut rng = rand::thread_rng();
-        let seed = rng.gen();
+//
+// The map starts empty, it is generated on the async compute pool when the
+// Generating state is entered, see generation.rs.
+//
+impl FromWorld for Map {
+    fn from_world(world: &mut World) -> Self {
+        let config = *world.get_resource_or_insert_with(MapConfig::default);
+        let seed = *world.get_resource_or_insert_with(WorldSeed::default);
+        let settings = *world.get_resource_or_insert_with(MapGenerationSettings::default);
 
-        println!("Map seed is {}.", seed);
+        settings.map_builder(seed, &config).build()
+    }
+}
 
-        let mut map = MapBuilder::new()
-        .with_seed(seed)
-        .with_frequency(0.03)
-        .with_gain(2.5)
-        .with_lacunarity(0.55)
-        .with_octaves(2)
-        .with_size(MAP_SIZE_X as usize * MAP_SIZE_Y as usize)
-        .build();
+impl Map {
+    pub fn is_generated(&self) -> bool {
+        !self.level_data.is_empty()
+    }
 
-        map.generate_noise_map();
-        map.generate_level();
+    //
+    // Generates the noise then the level, calling report with the fraction of
+    // the work done. Each noise map counts for a quarter, the level for the
+    // last one.
+    //
+    pub fn generate(&mut self, tiles: &TileDefinitions, biomes: &BiomeDefinitions, report: &mut dyn FnMut(f32)) {
+        self.noise_vector = self.fbm_noise(self.noise_seed);
+        report(0.25);
+        self.moisture_vector = self.fbm_noise(self.moisture_seed);
+        report(0.5);
+        self.temperature_vector = self.fbm_noise(self.temperature_seed);
+        report(0.75);
+        self.fill_level(tiles, biomes, &mut |rows| report(0.75 + 0.25 * rows));
+    }
 
-        map
+    //
+    // Generates a saved map again from its seeds and noise parameters, then
+    // lays its saved tiles over the generated ones so the level and what was
+    // explored stay as they were saved.
+    //
+    pub fn regenerate_saved(&mut self, tiles: &TileDefinitions, biomes: &BiomeDefinitions, report: &mut dyn FnMut(f32)) {
+        let saved_level = std::mem::take(&mut self.level_data);
+        self.generate(tiles, biomes, report);
+
+        if saved_level.len() == self.level_data.len() {
+            self.level_data = saved_level;
+            self.apply_tile_definitions(tiles);
+        } else {
+            warn!("The saved map doesn't match its size, using the generated one.");
+        }
     }
-}
 
-impl Map {
-    pub fn generate_noise_map(&mut self) {
-        self.noise_vector = NoiseBuilder::fbm_2d(self.map_size, self.map_size)
-        .with_seed(self.noise_seed)
+    fn fbm_noise(&self, seed: i32) -> Vec<f32> {
+        NoiseBuilder::fbm_2d(self.width, self.height)
+        .with_seed(seed)
         .with_freq(self.noise_frequency)
         .with_lacunarity(self.noise_lacunarity)
         .with_gain(self.noise_gain)
         .with_octaves(self.noise_octaves)
-        .generate_scaled(0.0, 1.0);
-    }
-    /*
-    pub fn map_max_size(&self) -> f32 {
-        self.map_size as f32 * TILE_SIZE as f32
-    }
-    */
-    pub fn generate_level(&mut self) {
-        for y in 0..self.map_size {
-            for x in 0..self.map_size {
-                let map_value = self.noise_vector[y * self.map_size + x];
-                let tile_x_pos = x * TILE_SIZE as usize;
-                let tile_y_pos = y * TILE_SIZE as usize;
-                let tile_type = self.biome(map_value);
+        .generate_scaled(0.0, 1.0)
+    }
+
+    pub fn generate_level(&mut self, tiles: &TileDefinitions, biomes: &BiomeDefinitions) {
+        self.fill_level(tiles, biomes, &mut |_| {});
+    }
+
+    // Calls report with the fraction of the rows done.
+    fn fill_level(&mut self, tiles: &TileDefinitions, biomes: &BiomeDefinitions, report: &mut dyn FnMut(f32)) {
+        self.level_data.clear();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let elevation = self.noise_vector[index];
+                // The temperature noise is only a variation around the latitude.
+                let temperature = biome::temperature(
+                    self.temperature_vector[index],
+                    y,
+                    self.height,
+                    elevation,
+                    biomes,
+                );
+                let tile_type = biome::biome(
+                    elevation,
+                    self.moisture_vector[index],
+                    temperature,
+                    biomes,
+                );
                 self.level_data
-                .push(TileInfo::new(tile_x_pos, tile_y_pos, tile_type));
+                .push(TileInfo::new(x, y, tile_type, tiles));
             }
+            report((y + 1) as f32 / self.height as f32);
         }
     }
 
-    fn biome(&self, map_elevation: f32) -> TileType {
-        if map_elevation < 0.1 {
-            TileType::DeepWater
-        } else if map_elevation < 0.2 {
-            TileType::Shore
-        } else if map_elevation < 0.3 {
-            TileType::Grass
-        } else if map_elevation < 0.5 {
-            TileType::Forest
-        } else if map_elevation < 0.8 {
-            TileType::Savannah
-        } else if map_elevation < 0.9 {
-            TileType::Sand
-        } else if map_elevation < 0.95 {
-            TileType::Rock
-        } else {
-            TileType::Mountain
+    pub fn apply_tile_definitions(&mut self, tiles: &TileDefinitions) {
+        for tile_info in self.level_data.iter_mut() {
+            tile_info.apply_definitions(tiles);
         }
     }
 
-    pub fn get_tileinfo_at(&self, x: usize, y: usize) -> TileInfo {
-        self.level_data[y * self.map_size + x as usize]
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
     }
 
-    #[allow(dead_code)]
-    pub fn save_image(self) {
-        let mut img = Image::new(self.map_size as u32, self.map_size as u32);
+    pub fn get_tileinfo_at(&self, x: i32, y: i32) -> Option<TileInfo> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        self.level_data.get(y as usize * self.width + x as usize).copied()
+    }
 
-        for x in 0..self.map_size - 1 {
-            for y in 0..self.map_size - 1 {
-                let height = self.noise_vector[x * self.map_size + y];
-                let color = 256.0 * height;
-                img.set_pixel(
-                    x as u32,
-                    y as u32,
-                    bmp::Pixel::new(color as u8, color as u8, color as u8),
-                );
-            }
+    pub fn get_tileinfo_mut(&mut self, x: i32, y: i32) -> Option<&mut TileInfo> {
+        if !self.in_bounds(x, y) {
+            return None;
+        }
+        self.level_data.get_mut(y as usize * self.width + x as usize)
+    }
+
+    pub fn explored_count(&self) -> usize {
+        self.level_data.iter().filter(|tile_info| tile_info.explored).count()
+    }
+
+    // Elevation from 0 to 1, None until the noise is generated.
+    pub fn elevation_at(&self, x: i32, y: i32) -> Option<f32> {
+        if !self.in_bounds(x, y) {
+            return None;
         }
-        let _ = img.save("map.bmp");
+        self.noise_vector.get(y as usize * self.width + x as usize).copied()
+    }
+
+    //
+    // Writes a preview of the map to path, as a PNG or a BMP depending on its
+    // extension. See export.rs.
+    //
+    pub fn save_image(&self, path: &str, options: ExportOptions) -> Result<(), String> {
+        MapImage::render(self, options)?.save(path)
     }
 }
 
 //
-// System used to render the map.
+// System filling the terrain layer of the Tilemap once its atlas is ready.
 //
-pub fn render_map(
-    mut commands: Commands,
-    mut map_sprite_handles: ResMut<TileSpriteHandles>,
-    asset_server: Res<AssetServer>,
+pub fn generate_random_world(
+    mut map_state: ResMut<MapState>,
     map: Res<Map>,
-    tile_data: Res<TileData>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut textures: ResMut<Assets<Texture>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    tile_atlas: Res<TileAtlas>,
+    mut query: Query<&mut Tilemap>,
 ) {
-    if map_sprite_handles.atlas_loaded {
+    if map_state.spawned || !map.is_generated() || !tile_atlas.is_loaded() {
         return;
     }
 
-    let mut texture_atlas_builder = TextureAtlasBuilder::default();
-    if let LoadState::Loaded =
-        asset_server.get_group_load_state(map_sprite_handles.handles.iter().map(|handle| handle.id))
-    {
-        for texture_id in map_sprite_handles.handles.iter() {
-            let texture = textures.get(texture_id).unwrap();
-            texture_atlas_builder.add_texture(texture_id.clone_weak().typed::<Texture>(), &texture);
-        }
-
-        let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
-        let _atlas_handle = texture_atlases.add(texture_atlas);
-
-        for y in 0..MAP_SIZE_Y as usize {
-            for x in 0..MAP_SIZE_X as usize {
-                let tile_info = map.get_tileinfo_at(x, y);
-                let transform = Transform::from_translation(Vec3::new(
-                    x as f32 * TILE_SIZE as f32 + TILE_SIZE as f32 / 2.0,
-                    y as f32 * TILE_SIZE as f32 + TILE_SIZE as f32 / 2.0,
-                    1.0,
-                ));
-
-                let handle: Handle<Texture> = asset_server.get_handle(&*tile_data.get_path(tile_info.tile_type));
-
-                commands
-                    .spawn_bundle(SpriteBundle {
-                        transform: transform,
-                        material: materials.add(handle.into()),
-                        ..Default::default()
+    for mut tilemap in query.iter_mut() {
+        map_state.map_loaded = true;
+
+        let mut tiles = Vec::with_capacity(map.width * map.height);
+        for y in 0..map.height as i32 {
+            for x in 0..map.width as i32 {
+                let tile_info = match map.get_tileinfo_at(x, y) {
+                    Some(tile_info) => tile_info,
+                    None => continue,
+                };
+
+                if !tile_info.walkable {
+                    map_state.collisions.insert((x, y));
+                }
+
+                if let Some(sprite_index) = tile_atlas.index_of(tile_info.tile_type) {
+                    tiles.push(Tile {
+                        point: (x, y),
+                        sprite_order: 0,
+                        sprite_index,
+                        tint: Color::WHITE,
                     });
+                }
             }
         }
 
-        map_sprite_handles.atlas_loaded = true;
+        if let Err(error) = tilemap.insert_tiles(tiles) {
+            error!("Unable to populate the tilemap: {:?}", error);
+            continue;
+        }
+
+        map_state.spawned = true;
+    }
+}
+#[cfg(test)]
+impl Map {
+    //
+    // Hand-made map for the tests, one character per tile and the first row at
+    // y = 0: '.' grass, 'd' dirt, 'f' forest, 's' shore, '#' rock,
+    // 'm' mountain, 'w' shallow water and '~' deep water.
+    //
+    pub fn from_rows(rows: &[&str]) -> Map {
+        let tiles = TileDefinitions::default();
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        let mut map = MapBuilder::new().with_size(width, height).build();
+
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row.len(), width, "the rows of a test map have the same length");
+            for (x, character) in row.chars().enumerate() {
+                let tile_type = match character {
+                    '.' => TileType::Grass,
+                    'd' => TileType::Dirt,
+                    'f' => TileType::Forest,
+                    's' => TileType::Shore,
+                    '#' => TileType::Rock,
+                    'm' => TileType::Mountain,
+                    'w' => TileType::ShallowWater,
+                    '~' => TileType::DeepWater,
+                    _ => panic!("unknown tile {:?} in a test map", character),
+                };
+                map.level_data.push(TileInfo::new(x, y, tile_type, &tiles));
+            }
+        }
+        map
     }
 }
 
-// pub fn scroll_map_system(
-//     cursor_state: Res<CursorState>,
-// ) {
-//     let camera_entity = cursor_state.camera_e;
-//     //TODO: make the player movement system send a message to scroll the map.
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(seed: u64) -> Map {
+        let config = MapConfig {
+            width: 32,
+            height: 24,
+            ..MapConfig::default()
+        };
+        let mut map = MapGenerationSettings::default()
+            .map_builder(WorldSeed(seed), &config)
+            .build();
+        map.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        map
+    }
 
-pub fn generate_random_world(
-    mut commands: Commands,
-    mut map_state: ResMut<MapState>,
-    texture_atlases: Res<Assets<TextureAtlas>>,
-    asset_server: Res<AssetServer>,
-    mut query: Query<&mut Tilemap>,
-) {
+    #[test]
+    fn the_same_seed_gives_the_same_level() {
+        let first = generate(1234);
+        let second = generate(1234);
+        assert_eq!(first.level_data.len(), 32 * 24);
+        assert_eq!(first.level_data, second.level_data);
+        assert_eq!(
+            ron::ser::to_string(&first.level_data).unwrap(),
+            ron::ser::to_string(&second.level_data).unwrap()
+        );
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn different_seeds_give_different_levels() {
+        let first = generate(1234);
+        let second = generate(4321);
+        assert_ne!(first.noise_vector, second.noise_vector);
+        assert_ne!(first.level_data, second.level_data);
+    }
+
+    #[test]
+    fn the_noise_seeds_come_from_the_world_seed() {
+        let first = MapBuilder::new().with_seed(WorldSeed(7)).build();
+        let second = MapBuilder::new().with_seed(WorldSeed(7)).build();
+        let other = MapBuilder::new().with_seed(WorldSeed(8)).build();
+        assert_eq!(first.noise_seed, second.noise_seed);
+        assert_eq!(first.moisture_seed, second.moisture_seed);
+        assert_eq!(first.temperature_seed, second.temperature_seed);
+        assert_ne!(first.noise_seed, other.noise_seed);
+    }
+}
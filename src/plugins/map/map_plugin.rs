@@ -1,42 +1,75 @@
 use bevy::{
-    asset::LoadState,
     prelude::*, 
     utils::HashSet,
-    sprite::{TextureAtlas, TextureAtlasBuilder},
 };
 use bevy_tilemap::prelude::*;
 
+use super::definitions::*;
+use super::fog::{fog_of_war_system, FogOfWar, FOG_SPRITE_ORDER};
+use super::fov::{fov_system, VisibleTiles};
+use super::generation::{
+    finish_map_generation_system, start_map_generation_system, MapGenerationProgress, MapGenerationSettings,
+};
+use super::map_config::MapConfig;
 use super::map_creator::*;
-
-const CHUNK_WIDTH: u32 = 16;
-const CHUNK_HEIGHT: u32 = 16;
-const TILEMAP_WIDTH: i32 = CHUNK_WIDTH as i32 * 40;
-const TILEMAP_HEIGHT: i32 = CHUNK_HEIGHT as i32 * 40;
+use super::tile_atlas::{build_tile_atlas, TileAtlas};
+use crate::state::{world_exists, AppState};
+use crate::world_seed::WorldSeed;
 
 pub struct MapPlugin;
 
 #[derive(Default, Clone)]
 pub struct TileSpriteHandles {
     pub handles: Vec<HandleUntyped>,
-    pub atlas_loaded: bool,
+    pub tilemap_spawned: bool,
 }
 
 #[derive(Default, Clone)]
 pub struct MapState {
     pub map_loaded: bool,
     pub spawned: bool,
+    // Unwalkable tiles of the level, the monsters are in OccupiedTiles.
     pub collisions: HashSet<(i32, i32)>,
 }
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<TileSpriteHandles>()
+            .init_resource::<MapGenerationSettings>()
+            .init_resource::<MapState>()
+            .init_resource::<MapConfig>()
+            .init_resource::<WorldSeed>()
+            .init_resource::<TileDefinitions>()
+            .init_resource::<BiomeDefinitions>()
+            .init_resource::<DefinitionHandles>()
+            .init_resource::<MapGenerationProgress>()
             .init_resource::<Map>()
-            .init_resource::<TileData>()
+            .init_resource::<TileAtlas>()
+            .init_resource::<VisibleTiles>()
+            .init_resource::<FogOfWar>()
+            .add_asset::<TileDefinitions>()
+            .add_asset::<BiomeDefinitions>()
+            .init_asset_loader::<TileDefinitionsLoader>()
+            .init_asset_loader::<BiomeDefinitionsLoader>()
             .add_plugins(TilemapDefaultPlugins)
             .add_startup_system(setup.system())
-            .add_system(load.system())
-            .add_system(generate_random_world.system());
+            .add_startup_system(load_definitions.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating).with_system(start_map_generation_system.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Generating).with_system(finish_map_generation_system.system()),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_exists.system())
+                    .with_system(apply_definitions_system.system())
+                    .with_system(build_tile_atlas.system())
+                    .with_system(load.system())
+                    .with_system(generate_random_world.system())
+                    .with_system(fov_system.system())
+                    .with_system(fog_of_war_system.system()),
+            );
     }
 }
 
@@ -44,36 +77,25 @@ fn setup(
     mut tile_sprite_handles: ResMut<TileSpriteHandles>,
     asset_server: Res<AssetServer>
 ) {
-    tile_sprite_handles.handles = asset_server.load_folder("assets/map_tiles").unwrap();
+    tile_sprite_handles.handles = asset_server.load_folder("map_tiles").unwrap();
 }
 
 fn load(
     mut commands: Commands,
     mut sprite_handles: ResMut<TileSpriteHandles>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut textures: ResMut<Assets<Texture>>,
-    asset_server: Res<AssetServer>,
+    config: Res<MapConfig>,
+    tile_atlas: Res<TileAtlas>,
 ) {
-    if sprite_handles.atlas_loaded {
+    if sprite_handles.tilemap_spawned {
         return;
     }
 
-    // Let's load our tile textures.
-    let mut texture_atlas_builder = TextureAtlasBuilder::default();
-    if let LoadState::Loaded = asset_server.get_group_load_state(sprite_handles.handles.iter().map(|handle| handle.id))
-    {
-        for handle in sprite_handles.handles.iter() {
-            let texture = textures.get(handle).unwrap();
-            texture_atlas_builder.add_texture(handle.clone_weak().typed::<Texture>(), &texture);
-        }
-
-        let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
-        let atlas_handle = texture_atlases.add(texture_atlas);
-
+    if let Some(atlas_handle) = tile_atlas.handle.clone() {
+        let (tilemap_width, tilemap_height) = config.tilemap_dimensions();
         let tilemap = Tilemap::builder()
-            .dimensions(TILEMAP_WIDTH as u32, TILEMAP_HEIGHT as u32)
-            .chunk_dimensions(CHUNK_WIDTH, CHUNK_HEIGHT, 1)
-            .texture_dimensions(32, 32)
+            .dimensions(tilemap_width, tilemap_height)
+            .chunk_dimensions(config.chunk_width, config.chunk_height, 1)
+            .texture_dimensions(config.tile_size, config.tile_size)
             .auto_chunk()
             .auto_spawn(2, 2)
             .add_layer(TilemapLayer {
@@ -82,6 +104,12 @@ fn load(
             },
             0,
             )
+            .add_layer(TilemapLayer {
+                kind: LayerKind::Dense,
+                ..Default::default()
+            },
+            FOG_SPRITE_ORDER,
+            )
             .texture_atlas(atlas_handle)
             .finish()
             .unwrap();
@@ -100,6 +128,6 @@ fn load(
             .insert_bundle(tilemap_components)
             .insert(Timer::from_seconds(0.075, true));
 
-        sprite_handles.atlas_loaded = true;
+        sprite_handles.tilemap_spawned = true;
     }
 }
\ No newline at end of file
@@ -1,2 +1,12 @@
+pub mod biome;
+pub mod coordinates;
+pub mod definitions;
+pub mod export;
+pub mod fog;
+pub mod fov;
+pub mod generation;
+pub mod map_config;
 pub mod map_creator;
 pub mod map_plugin;
+pub mod pathfinding;
+pub mod tile_atlas;
@@ -0,0 +1,498 @@
+use bevy::utils::HashSet;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::map_creator::{Map, TileInfo, TileType};
+
+// Costs are scaled so a diagonal step costs about sqrt(2) times a straight one.
+const STRAIGHT_STEP: u32 = 10;
+const DIAGONAL_STEP: u32 = 14;
+
+const STRAIGHT_NEIGHBOURS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL_NEIGHBOURS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+//
+// Tiles a walker can stand on. Water isn't walkable, only swimmers enter it.
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Terrain {
+    Land,
+    Water,
+    // Both land and water.
+    Any,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain::Land
+    }
+}
+
+impl Terrain {
+    pub fn allows(&self, tile_info: &TileInfo) -> bool {
+        let water = matches!(tile_info.tile_type, TileType::DeepWater | TileType::ShallowWater);
+        match self {
+            Terrain::Land => tile_info.walkable,
+            Terrain::Water => water,
+            Terrain::Any => tile_info.walkable || water,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathOptions {
+    pub diagonal: bool,
+    pub terrain: Terrain,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            diagonal: false,
+            terrain: Terrain::Land,
+        }
+    }
+}
+
+//
+// Read-only view of the map used by the searches. Blockers are tiles the
+// walker could stand on but that are occupied, like the monsters' tiles.
+//
+pub struct PathGrid<'a> {
+    map: &'a Map,
+    blockers: &'a HashSet<(i32, i32)>,
+    options: PathOptions,
+    // Tiles the walker knows about, the others are never crossed.
+    filter: Option<&'a dyn Fn(&TileInfo) -> bool>,
+}
+
+impl<'a> PathGrid<'a> {
+    pub fn new(map: &'a Map, blockers: &'a HashSet<(i32, i32)>, options: PathOptions) -> Self {
+        PathGrid {
+            map,
+            blockers,
+            options,
+            filter: None,
+        }
+    }
+
+    // The filter is only called on the tiles a search looks at.
+    pub fn with_filter(mut self, filter: &'a dyn Fn(&TileInfo) -> bool) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    fn tile_info(&self, tile: (i32, i32)) -> Option<TileInfo> {
+        self.map
+            .get_tileinfo_at(tile.0, tile.1)
+            .filter(|tile_info| self.filter.map_or(true, |filter| filter(tile_info)))
+    }
+
+    fn is_walkable(&self, tile: (i32, i32)) -> bool {
+        match self.tile_info(tile) {
+            Some(tile_info) => self.options.terrain.allows(&tile_info),
+            None => false,
+        }
+    }
+
+    //
+    // Whether a search can end on the tile. Besides the walker's own terrain,
+    // that's any walkable tile: a swimmer reaches the player on the shore.
+    //
+    fn is_goal(&self, tile: (i32, i32)) -> bool {
+        self.is_walkable(tile) || self.tile_info(tile).map_or(false, |tile_info| tile_info.walkable)
+    }
+
+    pub fn is_passable(&self, tile: (i32, i32)) -> bool {
+        self.is_walkable(tile) && !self.blockers.contains(&tile)
+    }
+
+    // Scaled cost of entering the tile.
+    pub fn entering_cost(&self, tile: (i32, i32), diagonal: bool) -> u32 {
+        let movement_cost = self
+            .map
+            .get_tileinfo_at(tile.0, tile.1)
+            .map(|tile_info| tile_info.movement_cost.max(1))
+            .unwrap_or(1);
+        let step = if diagonal { DIAGONAL_STEP } else { STRAIGHT_STEP };
+
+        step * movement_cost
+    }
+
+    //
+    // Tiles reachable in one step, with the cost of the step. The goal may be
+    // occupied (a monster chasing the player targets the player's tile), and
+    // diagonal steps can't cut the corner of an impassable tile.
+    //
+    fn neighbours(&self, tile: (i32, i32), goal: Option<(i32, i32)>) -> Vec<((i32, i32), u32)> {
+        let mut neighbours = Vec::with_capacity(8);
+        let can_enter = |next: (i32, i32)| {
+            self.is_passable(next) || (Some(next) == goal && self.is_goal(next))
+        };
+
+        for (dx, dy) in STRAIGHT_NEIGHBOURS.iter() {
+            let next = (tile.0 + dx, tile.1 + dy);
+            if can_enter(next) {
+                neighbours.push((next, self.entering_cost(next, false)));
+            }
+        }
+
+        if self.options.diagonal {
+            for (dx, dy) in DIAGONAL_NEIGHBOURS.iter() {
+                let next = (tile.0 + dx, tile.1 + dy);
+                let corners_clear =
+                    self.is_passable((tile.0 + dx, tile.1)) && self.is_passable((tile.0, tile.1 + dy));
+                if corners_clear && can_enter(next) {
+                    neighbours.push((next, self.entering_cost(next, true)));
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    fn heuristic(&self, from: (i32, i32), to: (i32, i32)) -> u32 {
+        let dx = (from.0 - to.0).abs() as u32;
+        let dy = (from.1 - to.1).abs() as u32;
+
+        if self.options.diagonal {
+            // Octile distance.
+            STRAIGHT_STEP * dx.max(dy) + (DIAGONAL_STEP - STRAIGHT_STEP) * dx.min(dy)
+        } else {
+            STRAIGHT_STEP * (dx + dy)
+        }
+    }
+
+    //
+    // A* search. Returns the steps from start to goal, start excluded, or None
+    // when the goal can't be reached.
+    //
+    pub fn find_path(&self, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        if start == goal {
+            return Some(Vec::new());
+        }
+        if !self.is_goal(goal) || !self.map.in_bounds(start.0, start.1) {
+            return None;
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+        let mut costs: HashMap<(i32, i32), u32> = HashMap::new();
+
+        costs.insert(start, 0);
+        open.push(Reverse((self.heuristic(start, goal), 0, start)));
+
+        while let Some(Reverse((_, cost, tile))) = open.pop() {
+            if tile == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(previous) = came_from.get(&current) {
+                    if *previous == start {
+                        break;
+                    }
+                    path.push(*previous);
+                    current = *previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            // A cheaper way to this tile was already expanded.
+            if cost > costs[&tile] {
+                continue;
+            }
+
+            for (next, step_cost) in self.neighbours(tile, Some(goal)) {
+                let next_cost = cost + step_cost;
+                if costs.get(&next).map_or(true, |known| next_cost < *known) {
+                    costs.insert(next, next_cost);
+                    came_from.insert(next, tile);
+                    open.push(Reverse((next_cost + self.heuristic(next, goal), next_cost, next)));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+//
+// Distance of every reachable tile to the closest goal, as paid by a walker
+// going to the goal. Walking downhill leads to a goal, walking uphill flees
+// from them.
+//
+pub struct DijkstraMap {
+    width: usize,
+    height: usize,
+    values: Vec<Option<u32>>,
+}
+
+impl DijkstraMap {
+    pub fn new(grid: &PathGrid, goals: &[(i32, i32)]) -> DijkstraMap {
+        let width = grid.map.width;
+        let height = grid.map.height;
+        let mut dijkstra_map = DijkstraMap {
+            width,
+            height,
+            values: vec![None; width * height],
+        };
+
+        let mut open = BinaryHeap::new();
+        for goal in goals.iter() {
+            if grid.is_goal(*goal) {
+                dijkstra_map.set(*goal, 0);
+                open.push(Reverse((0, *goal)));
+            }
+        }
+
+        while let Some(Reverse((cost, tile))) = open.pop() {
+            if dijkstra_map.get(tile).map_or(false, |known| cost > known) {
+                continue;
+            }
+
+            // The search goes backward: the walker steps from next to tile and
+            // pays for entering tile.
+            for (next, _) in grid.neighbours(tile, None) {
+                let diagonal = next.0 != tile.0 && next.1 != tile.1;
+                let next_cost = cost + grid.entering_cost(tile, diagonal);
+                if dijkstra_map.get(next).map_or(true, |known| next_cost < known) {
+                    dijkstra_map.set(next, next_cost);
+                    open.push(Reverse((next_cost, next)));
+                }
+            }
+        }
+
+        dijkstra_map
+    }
+
+    fn index(&self, tile: (i32, i32)) -> Option<usize> {
+        if tile.0 < 0 || tile.1 < 0 || tile.0 as usize >= self.width || tile.1 as usize >= self.height {
+            return None;
+        }
+        Some(tile.1 as usize * self.width + tile.0 as usize)
+    }
+
+    fn set(&mut self, tile: (i32, i32), value: u32) {
+        if let Some(index) = self.index(tile) {
+            self.values[index] = Some(value);
+        }
+    }
+
+    // Distance to the closest goal, None when no goal can be reached.
+    pub fn get(&self, tile: (i32, i32)) -> Option<u32> {
+        self.index(tile).and_then(|index| self.values[index])
+    }
+
+    // Tiles one step away with their distance and the cost of the step, with
+    // the stepping rules of the grid.
+    fn adjacent(&self, grid: &PathGrid, tile: (i32, i32)) -> Vec<(u32, u32, (i32, i32))> {
+        grid.neighbours(tile, None)
+            .into_iter()
+            .filter_map(|(next, step_cost)| self.get(next).map(|value| (value, step_cost, next)))
+            .collect()
+    }
+
+    // Neighbour on the cheapest way to a goal, if any is closer than the tile.
+    pub fn downhill(&self, grid: &PathGrid, tile: (i32, i32)) -> Option<(i32, i32)> {
+        let current = self.get(tile).unwrap_or(u32::MAX);
+        self.adjacent(grid, tile)
+            .into_iter()
+            .filter(|(value, _, _)| *value < current)
+            .map(|(value, step_cost, next)| (value + step_cost, next))
+            .min()
+            .map(|(_, next)| next)
+    }
+
+    // Neighbour getting the farthest from the goals, if any is farther than the tile.
+    pub fn uphill(&self, grid: &PathGrid, tile: (i32, i32)) -> Option<(i32, i32)> {
+        let current = self.get(tile).unwrap_or(0);
+        self.adjacent(grid, tile)
+            .into_iter()
+            .filter(|(value, _, _)| *value > current)
+            .map(|(value, _, next)| (value, next))
+            .max()
+            .map(|(_, next)| next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRAIGHT: PathOptions = PathOptions {
+        diagonal: false,
+        terrain: Terrain::Land,
+    };
+    const DIAGONAL: PathOptions = PathOptions {
+        diagonal: true,
+        terrain: Terrain::Land,
+    };
+
+    fn path(map: &Map, options: PathOptions, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        let blockers = HashSet::default();
+        PathGrid::new(map, &blockers, options).find_path(start, goal)
+    }
+
+    #[test]
+    fn straight_paths_exclude_the_start() {
+        let map = Map::from_rows(&["....."]);
+        assert_eq!(path(&map, STRAIGHT, (0, 0), (4, 0)), Some(vec![(1, 0), (2, 0), (3, 0), (4, 0)]));
+        assert_eq!(path(&map, STRAIGHT, (2, 0), (2, 0)), Some(vec![]));
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let map = Map::from_rows(&[".#.", ".#.", "..."]);
+        let steps = path(&map, STRAIGHT, (0, 0), (2, 0)).unwrap();
+        assert_eq!(steps, vec![(0, 1), (0, 2), (1, 2), (2, 2), (2, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn diagonals_shorten_paths() {
+        let map = Map::from_rows(&["...", "...", "..."]);
+        assert_eq!(path(&map, DIAGONAL, (0, 0), (2, 2)), Some(vec![(1, 1), (2, 2)]));
+        assert_eq!(path(&map, STRAIGHT, (0, 0), (2, 2)).map(|steps| steps.len()), Some(4));
+    }
+
+    #[test]
+    fn diagonals_cant_cut_corners() {
+        let map = Map::from_rows(&["..", "#."]);
+        assert_eq!(path(&map, DIAGONAL, (0, 0), (1, 1)), Some(vec![(1, 0), (1, 1)]));
+    }
+
+    #[test]
+    fn unreachable_goals_have_no_path() {
+        let map = Map::from_rows(&[".#."]);
+        assert_eq!(path(&map, DIAGONAL, (0, 0), (2, 0)), None);
+        // Unwalkable or outside of the map.
+        assert_eq!(path(&map, STRAIGHT, (0, 0), (1, 0)), None);
+        assert_eq!(path(&map, STRAIGHT, (0, 0), (5, 0)), None);
+    }
+
+    #[test]
+    fn blockers_are_avoided_but_can_be_the_goal() {
+        let map = Map::from_rows(&["..."]);
+        let mut blockers = HashSet::default();
+        blockers.insert((1, 0));
+        let grid = PathGrid::new(&map, &blockers, STRAIGHT);
+        assert_eq!(grid.find_path((0, 0), (2, 0)), None);
+        assert_eq!(grid.find_path((0, 0), (1, 0)), Some(vec![(1, 0)]));
+    }
+
+    #[test]
+    fn filtered_out_tiles_are_never_crossed() {
+        let mut map = Map::from_rows(&["...", "..."]);
+        map.get_tileinfo_mut(1, 0).unwrap().explored = true;
+        let blockers = HashSet::default();
+        let explored = |tile_info: &TileInfo| tile_info.x != 1 || tile_info.explored;
+        let grid = PathGrid::new(&map, &blockers, DIAGONAL).with_filter(&explored);
+        // Around the unexplored tile, without cutting its corners.
+        assert_eq!(grid.find_path((0, 1), (2, 1)), Some(vec![(0, 0), (1, 0), (2, 0), (2, 1)]));
+        assert_eq!(grid.find_path((0, 1), (1, 1)), None);
+    }
+
+    #[test]
+    fn paths_avoid_costly_tiles() {
+        let map = Map::from_rows(&[".ff.", "...."]);
+        let steps = path(&map, STRAIGHT, (0, 0), (3, 0)).unwrap();
+        assert_eq!(steps, vec![(0, 1), (1, 1), (2, 1), (3, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn dijkstra_distances_are_paid_by_the_walker() {
+        // Leaving the forest costs a grass step, entering it costs a forest one.
+        let map = Map::from_rows(&["f..", "..f"]);
+        let blockers = HashSet::default();
+        let grid = PathGrid::new(&map, &blockers, STRAIGHT);
+
+        let dijkstra_map = DijkstraMap::new(&grid, &[(2, 0)]);
+        assert_eq!(dijkstra_map.get((2, 0)), Some(0));
+        assert_eq!(dijkstra_map.get((1, 0)), Some(10));
+        assert_eq!(dijkstra_map.get((0, 0)), Some(20));
+        assert_eq!(dijkstra_map.get((2, 1)), Some(10));
+
+        let dijkstra_map = DijkstraMap::new(&grid, &[(0, 1)]);
+        assert_eq!(dijkstra_map.get((1, 1)), Some(10));
+        assert_eq!(dijkstra_map.get((2, 1)), Some(20));
+        assert_eq!(dijkstra_map.get((1, 0)), Some(20));
+    }
+
+    #[test]
+    fn dijkstra_matches_a_star() {
+        let map = Map::from_rows(&[".ff.", "..f.", "s..."]);
+        let blockers = HashSet::default();
+        let grid = PathGrid::new(&map, &blockers, DIAGONAL);
+        let dijkstra_map = DijkstraMap::new(&grid, &[(3, 0)]);
+
+        // Walking downhill follows a path as cheap as the A* one.
+        let mut tile = (0, 0);
+        let mut walked = Vec::new();
+        while let Some(next) = dijkstra_map.downhill(&grid, tile) {
+            walked.push(next);
+            tile = next;
+        }
+        assert_eq!(tile, (3, 0));
+
+        let cost = |steps: &[(i32, i32)]| {
+            let mut from = (0, 0);
+            steps.iter().fold(0, |total, step| {
+                let diagonal = step.0 != from.0 && step.1 != from.1;
+                from = *step;
+                total + grid.entering_cost(*step, diagonal)
+            })
+        };
+        let a_star = grid.find_path((0, 0), (3, 0)).unwrap();
+        assert_eq!(cost(&walked[..]), cost(&a_star[..]));
+        assert_eq!(dijkstra_map.get((0, 0)), Some(cost(&a_star[..])));
+    }
+
+    #[test]
+    fn fleeing_cant_cut_corners() {
+        let map = Map::from_rows(&["...", ".#.", "..."]);
+        let blockers = HashSet::default();
+        let grid = PathGrid::new(&map, &blockers, DIAGONAL);
+        let dijkstra_map = DijkstraMap::new(&grid, &[(0, 0)]);
+
+        // (2, 1) is farther but past the corner of the rock.
+        assert_eq!(dijkstra_map.get((2, 1)), Some(30));
+        assert_eq!(dijkstra_map.uphill(&grid, (1, 0)), Some((2, 0)));
+    }
+
+    #[test]
+    fn dijkstra_ignores_unreachable_tiles() {
+        let map = Map::from_rows(&[".#."]);
+        let blockers = HashSet::default();
+        let grid = PathGrid::new(&map, &blockers, DIAGONAL);
+        let dijkstra_map = DijkstraMap::new(&grid, &[(0, 0)]);
+
+        assert_eq!(dijkstra_map.get((2, 0)), None);
+        assert_eq!(dijkstra_map.uphill(&grid, (2, 0)), None);
+        assert_eq!(dijkstra_map.downhill(&grid, (2, 0)), None);
+    }
+
+    #[test]
+    fn swimmers_stay_in_the_water() {
+        let map = Map::from_rows(&["~~w..", "~#~~."]);
+        let water = PathOptions {
+            diagonal: false,
+            terrain: Terrain::Water,
+        };
+        assert_eq!(path(&map, water, (0, 0), (3, 1)), Some(vec![(1, 0), (2, 0), (2, 1), (3, 1)]));
+        // Walkable tiles are out of reach, unless they are the goal.
+        assert_eq!(path(&map, water, (0, 0), (4, 0)), None);
+        assert_eq!(path(&map, water, (3, 1), (4, 1)), Some(vec![(4, 1)]));
+        assert_eq!(path(&map, STRAIGHT, (4, 0), (0, 0)), None);
+    }
+
+    #[test]
+    fn amphibians_go_anywhere_but_rocks() {
+        let map = Map::from_rows(&[".~w.", "#m#."]);
+        let any = PathOptions {
+            diagonal: false,
+            terrain: Terrain::Any,
+        };
+        assert_eq!(path(&map, any, (0, 0), (3, 1)), Some(vec![(1, 0), (2, 0), (3, 0), (3, 1)]));
+        assert_eq!(path(&map, any, (0, 0), (1, 1)), None);
+    }
+}
@@ -0,0 +1,149 @@
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::texture::{Extent3d, TextureDimension, TextureFormat},
+    sprite::{TextureAtlas, TextureAtlasBuilder},
+};
+use std::collections::HashMap;
+
+use super::map_config::MapConfig;
+use super::definitions::TileDefinitions;
+use super::map_creator::TileType;
+use super::map_plugin::TileSpriteHandles;
+
+//
+// The texture atlas shared by every map layer, built once all the tile
+// textures are loaded.
+//
+#[derive(Default)]
+pub struct TileAtlas {
+    pub handle: Option<Handle<TextureAtlas>>,
+    // Plain white tile, tinted by the fog of war layer.
+    pub fog_index: Option<usize>,
+    indices: HashMap<TileType, usize>,
+}
+
+impl TileAtlas {
+    pub fn is_loaded(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    pub fn index_of(&self, tile_type: TileType) -> Option<usize> {
+        self.indices.get(&tile_type).copied()
+    }
+}
+
+pub fn build_tile_atlas(
+    config: Res<MapConfig>,
+    mut tile_atlas: ResMut<TileAtlas>,
+    sprite_handles: Res<TileSpriteHandles>,
+    tile_definitions: Res<TileDefinitions>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut textures: ResMut<Assets<Texture>>,
+    asset_server: Res<AssetServer>,
+) {
+    if tile_atlas.is_loaded() {
+        return;
+    }
+
+    if let LoadState::Loaded =
+        asset_server.get_group_load_state(sprite_handles.handles.iter().map(|handle| handle.id))
+    {
+        let (texture_atlas, mut loaded) = pack_tile_atlas(
+            config.tile_size,
+            &tile_definitions,
+            &mut *textures,
+            |path: &str| asset_server.get_handle(path),
+        );
+        loaded.handle = Some(texture_atlases.add(texture_atlas));
+        *tile_atlas = loaded;
+    }
+}
+
+//
+// Packs the textures referenced by the tile definitions, in the TileType
+// order, and the fog tile. handle_of gives the texture handle of a path. The
+// handle of the returned TileAtlas is left empty.
+//
+pub fn pack_tile_atlas(
+    tile_size: u32,
+    tile_definitions: &TileDefinitions,
+    textures: &mut Assets<Texture>,
+    handle_of: impl Fn(&str) -> Handle<Texture>,
+) -> (TextureAtlas, TileAtlas) {
+    let mut texture_atlas_builder = TextureAtlasBuilder::default();
+
+    let mut tile_handles: Vec<(TileType, Handle<Texture>)> = Vec::new();
+    for tile_type in TileType::ALL.iter() {
+        let handle = handle_of(tile_definitions.get(*tile_type).texture.as_str());
+        match textures.get(&handle) {
+            Some(texture) => texture_atlas_builder.add_texture(handle.clone_weak(), texture),
+            None => warn!("The texture for {} is not loaded.", tile_type),
+        }
+        tile_handles.push((*tile_type, handle));
+    }
+
+    let fog_texture = Texture::new_fill(
+        Extent3d::new(tile_size, tile_size, 1),
+        TextureDimension::D2,
+        &[255, 255, 255, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let fog_handle = textures.add(fog_texture.clone());
+    texture_atlas_builder.add_texture(fog_handle.clone(), &fog_texture);
+
+    let texture_atlas = texture_atlas_builder.finish(textures).unwrap();
+
+    let mut tile_atlas = TileAtlas::default();
+    for (tile_type, handle) in tile_handles.iter() {
+        if let Some(index) = texture_atlas.get_texture_index(handle) {
+            tile_atlas.indices.insert(*tile_type, index);
+        }
+    }
+    tile_atlas.fog_index = texture_atlas.get_texture_index(&fog_handle);
+
+    (texture_atlas, tile_atlas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::AssetPlugin;
+
+    fn tile_texture(color: u8) -> Texture {
+        Texture::new_fill(
+            Extent3d::new(32, 32, 1),
+            TextureDimension::D2,
+            &[color, color, color, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    #[test]
+    fn every_tile_type_has_an_atlas_index() {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Texture>();
+        let mut textures = app.world_mut().get_resource_mut::<Assets<Texture>>().unwrap();
+
+        let definitions = TileDefinitions::default();
+        let mut handles: HashMap<String, Handle<Texture>> = HashMap::new();
+        for (color, tile_type) in TileType::ALL.iter().enumerate() {
+            let path = definitions.get(*tile_type).texture.clone();
+            let texture = tile_texture(color as u8 * 20);
+            handles.entry(path).or_insert_with(|| textures.add(texture));
+        }
+
+        let (texture_atlas, tile_atlas) =
+            pack_tile_atlas(32, &definitions, &mut *textures, |path: &str| handles[path].clone());
+
+        for tile_type in TileType::ALL.iter() {
+            let index = tile_atlas.index_of(*tile_type);
+            assert!(index.is_some(), "no atlas index for {}", tile_type);
+            assert_ne!(index, tile_atlas.fog_index);
+        }
+        let fog_index = tile_atlas.fog_index.expect("the fog tile is in the atlas");
+        assert!(fog_index < texture_atlas.len());
+    }
+}
@@ -0,0 +1,453 @@
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+
+use crate::camera::{clamp_to_map, view_size, MainCamera};
+use crate::combat::{Attack, Defense, GameOver, Health};
+use crate::events::Died;
+use crate::monster::Monster;
+use crate::player::Player;
+use crate::plugins::map::coordinates::{tile_to_world, world_to_tile};
+use crate::plugins::map::fov::VisibleTiles;
+use crate::plugins::map::map_config::MapConfig;
+use crate::plugins::map::map_creator::Map;
+use crate::state::{AppState, StateSystem};
+use crate::travel::StepAnimation;
+use crate::turn::{TakesTurn, TurnQueue};
+use crate::world_seed::WorldSeed;
+
+pub const SAVE_PATH: &str = "savegame.ron";
+pub const RUNS_PATH: &str = "runs.ron";
+// Bumped whenever the layout of SaveGame changes, older saves are ignored.
+pub const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSave {
+    pub tile: (i32, i32),
+    pub health: Health,
+    pub attack: Attack,
+    pub defense: Defense,
+    pub energy: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonsterSave {
+    // Key of the monster in MonsterDefinitions::monsters, its other stats
+    // come from the definition.
+    pub kind: String,
+    pub tile: (i32, i32),
+    pub health: Health,
+    pub energy: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub world_seed: u64,
+    // TurnQueue::ticks, the turn counter of the run.
+    pub ticks: u64,
+    pub map: Map,
+    pub player: PlayerSave,
+    pub monsters: Vec<MonsterSave>,
+}
+
+//
+// Writes the whole content to a temporary file then renames it over the
+// destination, so a killed process leaves either the old or the new file and
+// never a truncated one.
+//
+pub fn write_atomically(path: &str, content: &str) -> io::Result<()> {
+    let temporary_path = format!("{}.tmp", path);
+    let mut file = fs::File::create(&temporary_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, path)
+}
+
+pub fn delete_save(path: &str) {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => error!("Unable to delete the saved game {}: {}", path, error),
+    }
+}
+
+impl SaveGame {
+    pub fn load(path: &str) -> Option<SaveGame> {
+        let content = fs::read_to_string(path).ok()?;
+        let save: SaveGame = match ron::de::from_str(&content) {
+            Ok(save) => save,
+            Err(error) => {
+                warn!("Unable to read the saved game {}: {}", path, error);
+                return None;
+            }
+        };
+
+        if save.version != SAVE_VERSION {
+            warn!(
+                "The saved game {} has version {}, expected {}. Starting a new game.",
+                path, save.version, SAVE_VERSION
+            );
+            return None;
+        }
+
+        // Only the noise parameters of the map are saved, the noise is generated
+        // again with the world, see Map::regenerate_saved.
+        Some(save)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let content = ron::ser::to_string(self).map_err(|error| error.to_string())?;
+        write_atomically(path, &content).map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub seed: u64,
+    pub cause_of_death: String,
+    pub turns: u64,
+    pub tiles_explored: usize,
+}
+
+//
+// Every finished run, kept in runs.ron for the high scores.
+//
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunHistory {
+    pub runs: Vec<RunRecord>,
+}
+
+impl RunHistory {
+    pub fn load(path: &str) -> RunHistory {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return RunHistory::default(),
+        };
+
+        match ron::de::from_str(&content) {
+            Ok(history) => history,
+            Err(error) => {
+                warn!("Unable to read the run history {}: {}", path, error);
+                RunHistory::default()
+            }
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())?;
+        write_atomically(path, &content).map_err(|error| error.to_string())
+    }
+
+    // Best runs first: the most tiles explored, then the longest survival.
+    pub fn high_scores(&self, count: usize) -> Vec<RunRecord> {
+        let mut runs = self.runs.clone();
+        runs.sort_by(|a, b| {
+            b.tiles_explored
+                .cmp(&a.tiles_explored)
+                .then(b.turns.cmp(&a.turns))
+        });
+        runs.truncate(count);
+        runs
+    }
+}
+
+//
+// Deletes the saved game when the run starts: the run only lives in memory
+// until it is saved again on quit, so killing the game can't bring back an
+// earlier state.
+//
+pub fn claim_save_system() {
+    delete_save(SAVE_PATH);
+}
+
+//
+// Permadeath: the player's death deletes the saved game, then the run is
+// added to the history.
+//
+pub fn permadeath_system(
+    world_seed: Res<WorldSeed>,
+    map: Res<Map>,
+    turn_queue: Res<TurnQueue>,
+    mut died_events: EventReader<Died>,
+    players: Query<&Player>,
+) {
+    for died in died_events.iter() {
+        if players.get(died.entity).is_err() {
+            continue;
+        }
+
+        delete_save(SAVE_PATH);
+
+        let mut history = RunHistory::load(RUNS_PATH);
+        history.runs.push(RunRecord {
+            seed: world_seed.0,
+            cause_of_death: died.cause.clone(),
+            turns: turn_queue.turn(),
+            tiles_explored: map.explored_count(),
+        });
+        if let Err(error) = history.write(RUNS_PATH) {
+            error!("Unable to record the run in {}: {}", RUNS_PATH, error);
+        }
+    }
+}
+
+//
+// Parts of the loaded game applied once the entities exist. The monsters are
+// taken by spawn_monsters_system instead of placing new ones.
+//
+pub struct LoadedGame {
+    pub ticks: u64,
+    // Taken by start_map_generation_system, which generates it in the background.
+    pub map: Option<Map>,
+    pub player: Option<PlayerSave>,
+    pub monsters: Option<Vec<MonsterSave>>,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn restore_player_system(
+    mut commands: Commands,
+    config: Res<MapConfig>,
+    windows: Res<Windows>,
+    loaded_game: Option<ResMut<LoadedGame>>,
+    mut turn_queue: ResMut<TurnQueue>,
+    mut visible_tiles: ResMut<VisibleTiles>,
+    mut player_query: Query<(&mut Transform, &mut Health, &mut Attack, &mut Defense, &mut TakesTurn), With<Player>>,
+    mut camera_query: Query<&mut Transform, (With<MainCamera>, Without<Player>)>,
+) {
+    let mut loaded_game = match loaded_game {
+        Some(loaded_game) => loaded_game,
+        None => return,
+    };
+
+    if loaded_game.player.is_some() {
+        for (mut transform, mut health, mut attack, mut defense, mut takes_turn) in player_query.iter_mut() {
+            let saved = match loaded_game.player.take() {
+                Some(saved) => saved,
+                None => break,
+            };
+
+            transform.translation = tile_to_world(&config, saved.tile, transform.translation.z);
+            *health = saved.health;
+            *attack = saved.attack;
+            *defense = saved.defense;
+            takes_turn.energy = saved.energy;
+            turn_queue.ticks = loaded_game.ticks;
+            // Have the field of view computed from the restored position.
+            visible_tiles.origin = None;
+
+            if let Some(view_size) = view_size(&windows) {
+                let center = clamp_to_map(transform.translation.truncate(), view_size, &config);
+                for mut camera_transform in camera_query.iter_mut() {
+                    camera_transform.translation.x = center.x;
+                    camera_transform.translation.y = center.y;
+                }
+            }
+        }
+    }
+
+    if loaded_game.player.is_none() && loaded_game.monsters.is_none() && loaded_game.map.is_none() {
+        commands.remove_resource::<LoadedGame>();
+    }
+}
+
+//
+// Only a run in progress is saved: not while its map is being generated, and
+// not once it is over. The pause menu and the inventory are pushed over
+// Playing, quitting from them saves too.
+//
+pub fn should_save(state: &AppState, map: &Map) -> bool {
+    matches!(state, AppState::Playing | AppState::Paused | AppState::Inventory) && map.is_generated()
+}
+
+//
+// Writes the game when the app exits. Runs in the last stage so the AppExit
+// sent during the frame is seen before the app closes.
+//
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn save_on_exit_system(
+    config: Res<MapConfig>,
+    world_seed: Res<WorldSeed>,
+    map: Res<Map>,
+    turn_queue: Res<TurnQueue>,
+    state: Res<State<AppState>>,
+    game_over: Option<Res<GameOver>>,
+    mut exit_events: EventReader<AppExit>,
+    player_query: Query<(&Transform, Option<&StepAnimation>, &Health, &Attack, &Defense, &TakesTurn), With<Player>>,
+    monster_query: Query<(&Monster, &Transform, &Health, &TakesTurn)>,
+) {
+    if exit_events.iter().next().is_none() || game_over.is_some() {
+        return;
+    }
+    if !should_save(state.current(), &map) {
+        return;
+    }
+
+    let player = match player_query.iter().next() {
+        Some((transform, animation, health, attack, defense, takes_turn)) => PlayerSave {
+            // A step being animated was already paid for.
+            tile: world_to_tile(&config, &animation.map_or(transform.translation, |animation| animation.to)),
+            health: *health,
+            attack: *attack,
+            defense: *defense,
+            energy: takes_turn.energy,
+        },
+        None => return,
+    };
+
+    let monsters = monster_query
+        .iter()
+        .map(|(monster, transform, health, takes_turn)| MonsterSave {
+            kind: monster.kind.clone(),
+            tile: world_to_tile(&config, &transform.translation),
+            health: *health,
+            energy: takes_turn.energy,
+        })
+        .collect();
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        world_seed: world_seed.0,
+        ticks: turn_queue.ticks,
+        map: (*map).clone(),
+        player,
+        monsters,
+    };
+
+    match save.write(SAVE_PATH) {
+        Ok(()) => info!("Game saved to {}.", SAVE_PATH),
+        Err(error) => error!("Unable to save the game to {}: {}", SAVE_PATH, error),
+    }
+}
+
+//
+// Reads the saved game, if any, when the plugin is built. It must be added
+// before MapPlugin so the saved seed is used instead of a new one. The map
+// is restored by the world generation.
+//
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if let Some(save) = SaveGame::load(SAVE_PATH) {
+            info!("Loading the saved game {}.", SAVE_PATH);
+            app.insert_resource(WorldSeed(save.world_seed))
+                .insert_resource(LoadedGame {
+                    ticks: save.ticks,
+                    map: Some(save.map),
+                    player: Some(save.player),
+                    monsters: Some(save.monsters),
+                });
+        }
+
+        app.add_system(restore_player_system.system())
+            .add_system_set(SystemSet::on_enter(AppState::Generating).with_system(claim_save_system.system()))
+            // The run is recorded before the death leaves the Playing state.
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(permadeath_system.system().before(StateSystem::GameOver)),
+            )
+            .add_system_to_stage(CoreStage::Last, save_on_exit_system.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::map::definitions::{BiomeDefinitions, TileDefinitions};
+    use crate::plugins::map::generation::MapGenerationSettings;
+    use std::env;
+
+    fn temporary_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("bevy_tuto_{}_{}.ron", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn save_game() -> SaveGame {
+        let config = MapConfig {
+            width: 24,
+            height: 16,
+            ..Default::default()
+        };
+        let mut map = MapGenerationSettings::default().map_builder(WorldSeed(11), &config).build();
+        map.generate(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        for (x, y) in [(0, 0), (3, 2), (23, 15)].iter() {
+            map.get_tileinfo_mut(*x, *y).unwrap().explored = true;
+        }
+
+        SaveGame {
+            version: SAVE_VERSION,
+            world_seed: 11,
+            ticks: 1234,
+            map,
+            player: PlayerSave {
+                tile: (3, 2),
+                health: Health { current: 7, max: 20 },
+                attack: Attack(4),
+                defense: Defense(2),
+                energy: 40,
+            },
+            monsters: vec![
+                MonsterSave {
+                    kind: "wolf".to_string(),
+                    tile: (5, 6),
+                    health: Health::new(12),
+                    energy: 90,
+                },
+                MonsterSave {
+                    kind: "shark".to_string(),
+                    tile: (20, 1),
+                    health: Health { current: 3, max: 16 },
+                    energy: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn saved_games_load_back_unchanged() {
+        let path = temporary_path("round_trip");
+        let save = save_game();
+        save.write(&path).unwrap();
+        let loaded = SaveGame::load(&path);
+        delete_save(&path);
+
+        let mut loaded = loaded.expect("the saved game loads");
+        assert_eq!(loaded.map.explored_count(), 3);
+        loaded
+            .map
+            .regenerate_saved(&TileDefinitions::default(), &BiomeDefinitions::default(), &mut |_| {});
+        assert_eq!(loaded, save);
+    }
+
+    #[test]
+    fn saves_of_another_version_are_ignored() {
+        let path = temporary_path("old_version");
+        let mut save = save_game();
+        save.version = SAVE_VERSION + 1;
+        save.write(&path).unwrap();
+        let loaded = SaveGame::load(&path);
+        delete_save(&path);
+
+        assert!(loaded.is_none());
+    }
+
+    #[test]
+    fn only_runs_in_progress_are_saved() {
+        let generated = save_game().map;
+        let empty = Map::from_rows(&[]);
+        assert!(should_save(&AppState::Playing, &generated));
+        assert!(should_save(&AppState::Paused, &generated));
+        assert!(should_save(&AppState::Inventory, &generated));
+
+        // The world isn't there yet, or the run is over.
+        assert!(!should_save(&AppState::Playing, &empty));
+        assert!(!should_save(&AppState::Generating, &generated));
+        assert!(!should_save(&AppState::MainMenu, &generated));
+        assert!(!should_save(&AppState::GameOver, &generated));
+    }
+}
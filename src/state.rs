@@ -0,0 +1,401 @@
+use bevy::{app::AppExit, ecs::schedule::ShouldRun, prelude::*};
+
+use crate::combat::GameOver;
+use crate::input::{Action, ActionState, InputSystem};
+use crate::monster::MonstersSpawned;
+use crate::plugins::map::generation::MapGenerationProgress;
+use crate::plugins::map::map_plugin::MapState;
+use crate::save::{LoadedGame, RunHistory, RUNS_PATH};
+use crate::tooltip::TOOLTIP_FONT;
+
+const MENU_FONT_SIZE: f32 = 24.0;
+// Runs listed on the game over screen.
+const HIGH_SCORES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    // The level is built, see the map plugin.
+    Generating,
+    Playing,
+    // Paused and Inventory are pushed over Playing.
+    Paused,
+    Inventory,
+    GameOver,
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StateSystem {
+    GameOver,
+}
+
+//
+// Run criteria of the systems keeping the world up to date: they run in every
+// state but the main menu, where no run has started yet.
+//
+pub fn world_exists(state: Res<State<AppState>>) -> ShouldRun {
+    match state.current() {
+        AppState::MainMenu => ShouldRun::No,
+        _ => ShouldRun::Yes,
+    }
+}
+
+// Marker of the nodes of a menu screen, removed when leaving its state.
+pub struct MenuScreen;
+
+// Marker of the text of the generating screen.
+pub struct GenerationProgressText;
+
+fn spawn_menu(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    materials: &mut Assets<ColorMaterial>,
+    background: Color,
+    text: String,
+) -> Entity {
+    let mut text_entity = None;
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(background.into()),
+            ..Default::default()
+        })
+        .insert(MenuScreen)
+        .with_children(|parent| {
+            let entity = parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        text,
+                        TextStyle {
+                            font: asset_server.load(TOOLTIP_FONT),
+                            font_size: MENU_FONT_SIZE,
+                            color: Color::WHITE,
+                        },
+                        TextAlignment {
+                            horizontal: HorizontalAlign::Center,
+                            vertical: VerticalAlign::Center,
+                        },
+                    ),
+                    ..Default::default()
+                })
+                .id();
+            text_entity = Some(entity);
+        });
+    text_entity.expect("the menu text is spawned with the menu")
+}
+
+pub fn despawn_menu(mut commands: Commands, query: Query<Entity, With<MenuScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn spawn_main_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    loaded_game: Option<Res<LoadedGame>>,
+) {
+    let play = if loaded_game.is_some() { "continue the saved game" } else { "start a new game" };
+    let text = format!("Void destiny\n\nEnter: {}\nEsc: quit", play);
+    spawn_menu(&mut commands, &asset_server, &mut materials, Color::BLACK, text);
+}
+
+pub fn main_menu_input_system(
+    mut action_state: ResMut<ActionState>,
+    mut state: ResMut<State<AppState>>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    if action_state.consume(Action::Confirm) {
+        let _ = state.set(AppState::Generating);
+    } else if action_state.consume(Action::Pause) {
+        exit_events.send(AppExit);
+    }
+}
+
+pub fn spawn_generating_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let text = generating_text(0.0);
+    let text_entity = spawn_menu(&mut commands, &asset_server, &mut materials, Color::BLACK, text);
+    commands.entity(text_entity).insert(GenerationProgressText);
+}
+
+fn generating_text(fraction: f32) -> String {
+    format!("Generating the world... {}%", (fraction * 100.0).round() as u32)
+}
+
+pub fn generating_screen_system(
+    progress: Res<MapGenerationProgress>,
+    mut query: Query<&mut Text, With<GenerationProgressText>>,
+) {
+    if !progress.is_changed() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = generating_text(progress.fraction);
+    }
+}
+
+// The run starts once the terrain and the monsters are in place.
+pub fn generation_done_system(
+    map_state: Res<MapState>,
+    monsters_spawned: Res<MonstersSpawned>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if map_state.spawned && monsters_spawned.0 {
+        let _ = state.set(AppState::Playing);
+    }
+}
+
+pub fn playing_input_system(mut action_state: ResMut<ActionState>, mut state: ResMut<State<AppState>>) {
+    if action_state.consume(Action::Pause) {
+        let _ = state.push(AppState::Paused);
+    } else if action_state.consume(Action::Inventory) {
+        let _ = state.push(AppState::Inventory);
+    }
+}
+
+// The player's death ends the run.
+pub fn game_over_system(game_over: Option<Res<GameOver>>, mut state: ResMut<State<AppState>>) {
+    if game_over.is_some() {
+        let _ = state.set(AppState::GameOver);
+    }
+}
+
+pub fn spawn_pause_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let text = "Paused\n\nEsc: resume\nQ: save and quit".to_string();
+    spawn_menu(&mut commands, &asset_server, &mut materials, Color::rgba(0.0, 0.0, 0.0, 0.7), text);
+}
+
+pub fn pause_input_system(
+    mut action_state: ResMut<ActionState>,
+    mut state: ResMut<State<AppState>>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    if action_state.consume(Action::Pause) {
+        let _ = state.pop();
+    } else if action_state.consume(Action::Quit) {
+        exit_events.send(AppExit);
+    }
+}
+
+pub fn spawn_inventory(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let text = "Inventory\n\nYou carry nothing.\n\nEsc: close".to_string();
+    spawn_menu(&mut commands, &asset_server, &mut materials, Color::rgba(0.0, 0.0, 0.0, 0.7), text);
+}
+
+pub fn inventory_input_system(mut action_state: ResMut<ActionState>, mut state: ResMut<State<AppState>>) {
+    if action_state.consume(Action::Pause) || action_state.consume(Action::Inventory) {
+        let _ = state.pop();
+    }
+}
+
+pub fn spawn_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    game_over: Option<Res<GameOver>>,
+) {
+    let cause = game_over.map_or_else(String::new, |game_over| game_over.cause.clone());
+    let mut text = format!("You died, {}.\n\nBest runs\n", cause);
+    for (rank, run) in RunHistory::load(RUNS_PATH).high_scores(HIGH_SCORES).iter().enumerate() {
+        text.push_str(&format!(
+            "{}. {} tiles explored, {} turns, {} (seed {})\n",
+            rank + 1,
+            run.tiles_explored,
+            run.turns,
+            run.cause_of_death,
+            run.seed
+        ));
+    }
+    text.push_str("\nEnter: quit");
+
+    spawn_menu(&mut commands, &asset_server, &mut materials, Color::rgba(0.0, 0.0, 0.0, 0.8), text);
+}
+
+pub fn game_over_input_system(mut action_state: ResMut<ActionState>, mut exit_events: EventWriter<AppExit>) {
+    if action_state.consume(Action::Confirm) || action_state.consume(Action::Pause) {
+        exit_events.send(AppExit);
+    }
+}
+
+pub struct StatePlugin;
+
+impl Plugin for StatePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_state(AppState::MainMenu)
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(spawn_main_menu.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::MainMenu)
+                    .with_system(main_menu_input_system.system().after(InputSystem::Update)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(despawn_menu.system()))
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating).with_system(spawn_generating_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Generating)
+                    .with_system(generating_screen_system.system())
+                    .with_system(generation_done_system.system()),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Generating).with_system(despawn_menu.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(playing_input_system.system().after(InputSystem::Update))
+                    .with_system(game_over_system.system().label(StateSystem::GameOver)),
+            )
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(spawn_pause_menu.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Paused)
+                    .with_system(pause_input_system.system().after(InputSystem::Update)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(despawn_menu.system()))
+            .add_system_set(SystemSet::on_enter(AppState::Inventory).with_system(spawn_inventory.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Inventory)
+                    .with_system(inventory_input_system.system().after(InputSystem::Update)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Inventory).with_system(despawn_menu.system()))
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver).with_system(spawn_game_over_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::GameOver)
+                    .with_system(game_over_input_system.system().after(InputSystem::Update)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::AssetPlugin;
+    use crate::input::{InputConfig, InputMap};
+
+    fn app() -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<ColorMaterial>()
+            .insert_resource(InputMap::from_config(&InputConfig::default()))
+            .init_resource::<ActionState>()
+            .init_resource::<MapState>()
+            .init_resource::<MonstersSpawned>()
+            .init_resource::<MapGenerationProgress>()
+            .add_plugin(StatePlugin);
+        app.app.update();
+        app
+    }
+
+    fn current(app: &AppBuilder) -> AppState {
+        *app.app.world.get_resource::<State<AppState>>().unwrap().current()
+    }
+
+    fn menu_screens(app: &mut AppBuilder) -> usize {
+        let mut query = app.app.world.query_filtered::<(), With<MenuScreen>>();
+        query.iter(&app.app.world).count()
+    }
+
+    // Presses and releases the action, then runs a frame.
+    fn press(app: &mut AppBuilder, action: Action) {
+        let input_map = InputMap::from_config(&InputConfig::default());
+        let mut action_state = app.world_mut().get_resource_mut::<ActionState>().unwrap();
+        action_state.update(action, true, 0.0, &input_map);
+        action_state.update(action, false, 0.0, &input_map);
+        app.app.update();
+    }
+
+    fn start_playing(app: &mut AppBuilder) {
+        press(app, Action::Confirm);
+        app.world_mut().get_resource_mut::<MapState>().unwrap().spawned = true;
+        app.world_mut().get_resource_mut::<MonstersSpawned>().unwrap().0 = true;
+        app.app.update();
+        assert_eq!(current(app), AppState::Playing);
+    }
+
+    #[test]
+    fn the_menu_leads_to_the_generation_then_the_game() {
+        let mut app = app();
+        assert_eq!(current(&app), AppState::MainMenu);
+        assert_eq!(menu_screens(&mut app), 1);
+
+        press(&mut app, Action::Confirm);
+        assert_eq!(current(&app), AppState::Generating);
+
+        // Generating lasts until the terrain and the monsters are spawned.
+        app.app.update();
+        assert_eq!(current(&app), AppState::Generating);
+        app.world_mut().get_resource_mut::<MapState>().unwrap().spawned = true;
+        app.app.update();
+        assert_eq!(current(&app), AppState::Generating);
+        app.world_mut().get_resource_mut::<MonstersSpawned>().unwrap().0 = true;
+        app.app.update();
+        assert_eq!(current(&app), AppState::Playing);
+        assert_eq!(menu_screens(&mut app), 0);
+    }
+
+    #[test]
+    fn pause_is_pushed_over_the_game_and_popped() {
+        let mut app = app();
+        start_playing(&mut app);
+
+        press(&mut app, Action::Pause);
+        assert_eq!(current(&app), AppState::Paused);
+        assert_eq!(menu_screens(&mut app), 1);
+        // The press opening the menu doesn't close it too.
+        assert!(!app.app.world.get_resource::<ActionState>().unwrap().just_pressed(Action::Pause));
+        app.app.update();
+        assert_eq!(current(&app), AppState::Paused);
+
+        press(&mut app, Action::Pause);
+        assert_eq!(current(&app), AppState::Playing);
+        assert_eq!(menu_screens(&mut app), 0);
+    }
+
+    #[test]
+    fn the_inventory_is_pushed_over_the_game_and_popped() {
+        let mut app = app();
+        start_playing(&mut app);
+
+        press(&mut app, Action::Inventory);
+        assert_eq!(current(&app), AppState::Inventory);
+        assert_eq!(
+            app.app.world.get_resource::<State<AppState>>().unwrap().inactives(),
+            &[AppState::Playing]
+        );
+        app.app.update();
+        assert_eq!(current(&app), AppState::Inventory);
+
+        press(&mut app, Action::Inventory);
+        assert_eq!(current(&app), AppState::Playing);
+    }
+
+    #[test]
+    fn death_ends_the_game() {
+        let mut app = app();
+        start_playing(&mut app);
+
+        app.insert_resource(GameOver {
+            cause: "killed by a wolf".to_string(),
+        });
+        app.app.update();
+        assert_eq!(current(&app), AppState::GameOver);
+    }
+}
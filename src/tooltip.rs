@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+
+use crate::picking::{MouseLocation, PickingSystem};
+use crate::plugins::map::fov::VisibleTiles;
+use crate::plugins::map::map_creator::{Map, TileInfo};
+use crate::state::AppState;
+
+pub const TOOLTIP_FONT: &str = "fonts/DejaVuSansMono.ttf";
+const TOOLTIP_FONT_SIZE: f32 = 16.0;
+// Distance between the cursor and the tooltip, in pixels.
+const TOOLTIP_OFFSET: f32 = 16.0;
+
+// Marker of the text showing the details of the tile under the cursor.
+pub struct TileTooltip;
+
+pub fn spawn_tooltip(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(TOOLTIP_FONT),
+                    font_size: TOOLTIP_FONT_SIZE,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(TileTooltip);
+}
+
+pub fn tooltip_text(tile_info: &TileInfo, in_view: bool) -> String {
+    format!(
+        "{} ({}, {})\nwalkable: {}\nmovement cost: {}\n{}",
+        tile_info.tile_type,
+        tile_info.x,
+        tile_info.y,
+        if tile_info.walkable { "yes" } else { "no" },
+        tile_info.movement_cost,
+        // Only explored tiles have a tooltip, either seen now or remembered.
+        if in_view { "in view" } else { "remembered" },
+    )
+}
+
+//
+// Shows the tooltip next to the cursor. It stays hidden over unexplored
+// tiles so it doesn't reveal what the player hasn't seen yet.
+//
+pub fn tooltip_system(
+    mouse_location: Res<MouseLocation>,
+    map: Res<Map>,
+    visible_tiles: Res<VisibleTiles>,
+    mut query: Query<(&mut Text, &mut Style, &mut Visible), With<TileTooltip>>,
+) {
+    let hovered = match (mouse_location.screen, mouse_location.tile) {
+        (Some(screen), Some((x, y))) => map
+            .get_tileinfo_at(x, y)
+            .filter(|tile_info| tile_info.explored)
+            .map(|tile_info| (screen, tile_info, visible_tiles.contains(x, y))),
+        _ => None,
+    };
+
+    for (mut text, mut style, mut visible) in query.iter_mut() {
+        match hovered {
+            Some((screen, tile_info, in_view)) => {
+                let value = tooltip_text(&tile_info, in_view);
+                if text.sections[0].value != value {
+                    text.sections[0].value = value;
+                }
+                style.position.left = Val::Px(screen.x + TOOLTIP_OFFSET);
+                style.position.bottom = Val::Px(screen.y + TOOLTIP_OFFSET);
+                visible.is_visible = true;
+            }
+            None => {
+                if visible.is_visible {
+                    visible.is_visible = false;
+                }
+            }
+        }
+    }
+}
+
+// The tooltip would stay over the menus otherwise.
+pub fn hide_tooltip_system(mut query: Query<&mut Visible, With<TileTooltip>>) {
+    for mut visible in query.iter_mut() {
+        visible.is_visible = false;
+    }
+}
+
+pub struct TooltipPlugin;
+
+impl Plugin for TooltipPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(spawn_tooltip.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(tooltip_system.system().after(PickingSystem::MouseLocation)),
+            )
+            .add_system_set(SystemSet::on_pause(AppState::Playing).with_system(hide_tooltip_system.system()))
+            .add_system_set(SystemSet::on_exit(AppState::Playing).with_system(hide_tooltip_system.system()));
+    }
+}
@@ -0,0 +1,211 @@
+use bevy::{prelude::*, utils::HashSet};
+use std::collections::VecDeque;
+
+use crate::events::TileInspected;
+use crate::monster::OccupiedTiles;
+use crate::picking::PickingSystem;
+use crate::player::Player;
+use crate::plugins::map::coordinates::{tile_to_world, world_to_tile};
+use crate::plugins::map::fov::VisibleTiles;
+use crate::plugins::map::map_config::MapConfig;
+use crate::plugins::map::map_creator::{Map, TileInfo};
+use crate::plugins::map::map_plugin::MapState;
+use crate::plugins::map::pathfinding::{PathGrid, PathOptions};
+use crate::state::{world_exists, AppState};
+use crate::turn::{TakesTurn, TurnQueue, TurnSystem, ACTION_COST};
+
+// Duration of the animation of a single step, in seconds.
+const STEP_DURATION: f32 = 0.08;
+
+// Marker of the entities interrupting the player's travel when they show up.
+pub struct InterruptsTravel;
+
+//
+// Steps left to reach the tile the player clicked.
+//
+#[derive(Default)]
+pub struct TravelPlan {
+    pub path: VecDeque<(i32, i32)>,
+}
+
+impl TravelPlan {
+    pub fn is_travelling(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    pub fn cancel(&mut self) {
+        self.path.clear();
+    }
+}
+
+// Slides an entity from one tile to the next.
+pub struct StepAnimation {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub timer: Timer,
+}
+
+impl StepAnimation {
+    pub fn new(from: Vec3, to: Vec3) -> StepAnimation {
+        StepAnimation {
+            from,
+            to,
+            timer: Timer::from_seconds(STEP_DURATION, false),
+        }
+    }
+}
+
+//
+// Plans a path to the clicked tile. Only explored tiles can be targeted or
+// crossed, so travelling never reveals anything about the unexplored map.
+//
+pub fn travel_planning_system(
+    config: Res<MapConfig>,
+    map: Res<Map>,
+    map_state: Res<MapState>,
+    mut travel_plan: ResMut<TravelPlan>,
+    mut events: EventReader<TileInspected>,
+    query: Query<&Transform, With<Player>>,
+) {
+    let target = match events.iter().last() {
+        Some(event) => event,
+        None => return,
+    };
+
+    if !target.tile_info.explored {
+        info!("Can't travel to an unexplored tile.");
+        return;
+    }
+
+    for transform in query.iter() {
+        let start = world_to_tile(&config, &transform.translation);
+
+        // Diagonal steps, like the player's moves.
+        let options = PathOptions {
+            diagonal: true,
+            ..Default::default()
+        };
+        let explored = |tile_info: &TileInfo| tile_info.explored;
+        let grid = PathGrid::new(&map, &map_state.collisions, options).with_filter(&explored);
+        match grid.find_path(start, target.tile) {
+            Some(path) => travel_plan.path = path.into_iter().collect(),
+            None => {
+                info!("There is no known way to {:?}.", target.tile);
+                travel_plan.cancel();
+            }
+        }
+    }
+}
+
+//
+// Takes the next step of the travel plan, one per player turn.
+//
+pub fn travel_step_system(
+    mut commands: Commands,
+    config: Res<MapConfig>,
+    map_state: Res<MapState>,
+    occupied: Res<OccupiedTiles>,
+    mut travel_plan: ResMut<TravelPlan>,
+    mut turn_queue: ResMut<TurnQueue>,
+    mut query: Query<(Entity, &Transform, &mut TakesTurn), (With<Player>, Without<StepAnimation>)>,
+) {
+    if !travel_plan.is_travelling() {
+        return;
+    }
+
+    for (entity, transform, mut takes_turn) in query.iter_mut() {
+        if !turn_queue.is_turn_of(entity) {
+            continue;
+        }
+
+        let next = match travel_plan.path.pop_front() {
+            Some(next) => next,
+            None => return,
+        };
+
+        // Something moved in the way since the path was planned.
+        if map_state.collisions.contains(&next) || occupied.0.contains(&next) {
+            info!("The way is blocked.");
+            travel_plan.cancel();
+            return;
+        }
+
+        let destination = tile_to_world(&config, next, transform.translation.z);
+        commands
+            .entity(entity)
+            .insert(StepAnimation::new(transform.translation, destination));
+        turn_queue.end_turn(&mut takes_turn, ACTION_COST);
+    }
+}
+
+pub fn step_animation_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Transform, &mut StepAnimation)>,
+) {
+    for (entity, mut transform, mut animation) in query.iter_mut() {
+        animation.timer.tick(time.delta());
+        let progress = animation.timer.percent();
+        transform.translation = animation.from.lerp(animation.to, progress);
+
+        if animation.timer.finished() {
+            transform.translation = animation.to;
+            commands.entity(entity).remove::<StepAnimation>();
+        }
+    }
+}
+
+//
+// Stops travelling when a key or a gamepad button is pressed, or when
+// something interrupting the travel comes into view.
+//
+pub fn travel_interrupt_system(
+    config: Res<MapConfig>,
+    keyboard_input: Res<Input<KeyCode>>,
+    button_input: Res<Input<GamepadButton>>,
+    visible_tiles: Res<VisibleTiles>,
+    mut travel_plan: ResMut<TravelPlan>,
+    mut in_view: Local<HashSet<Entity>>,
+    query: Query<(Entity, &Transform), With<InterruptsTravel>>,
+) {
+    let mut now_in_view = HashSet::default();
+    for (entity, transform) in query.iter() {
+        let (x, y) = world_to_tile(&config, &transform.translation);
+        if visible_tiles.contains(x, y) {
+            now_in_view.insert(entity);
+        }
+    }
+
+    if travel_plan.is_travelling() {
+        if keyboard_input.get_just_pressed().next().is_some()
+            || button_input.get_just_pressed().next().is_some()
+        {
+            info!("Travel interrupted.");
+            travel_plan.cancel();
+        } else if now_in_view.iter().any(|entity| !in_view.contains(entity)) {
+            info!("Something comes into view, travel interrupted.");
+            travel_plan.cancel();
+        }
+    }
+
+    *in_view = now_in_view;
+}
+
+pub struct TravelPlugin;
+
+impl Plugin for TravelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<TravelPlan>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(travel_planning_system.system().after(PickingSystem::MouseLocation))
+                    .with_system(travel_interrupt_system.system())
+                    .with_system(travel_step_system.system().after(TurnSystem::Schedule)),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_exists.system())
+                    .with_system(step_animation_system.system()),
+            );
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::state::AppState;
+use crate::travel::StepAnimation;
+
+// Energy spent by an action, an actor acts once it has gathered that much.
+pub const ACTION_COST: u32 = 100;
+// Speed of an average actor, which acts once every ACTION_COST / NORMAL_SPEED ticks.
+pub const NORMAL_SPEED: u32 = 10;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TurnSystem {
+    Schedule,
+}
+
+//
+// Component of every entity acting in the turn order. It gains `speed` energy
+// every tick and acts as soon as it has ACTION_COST energy.
+//
+#[derive(Debug, Clone, Copy)]
+pub struct TakesTurn {
+    pub speed: u32,
+    pub energy: u32,
+}
+
+impl TakesTurn {
+    pub fn new(speed: u32) -> TakesTurn {
+        TakesTurn { speed, energy: 0 }
+    }
+}
+
+#[derive(Default)]
+pub struct TurnQueue {
+    // Entity allowed to act, None until the scheduler picks the next one.
+    pub current: Option<Entity>,
+    // Ticks elapsed since the start of the run.
+    pub ticks: u64,
+    ready: VecDeque<Entity>,
+}
+
+impl TurnQueue {
+    pub fn is_turn_of(&self, entity: Entity) -> bool {
+        self.current == Some(entity)
+    }
+
+    // Number of turns of an average actor since the start of the run.
+    pub fn turn(&self) -> u64 {
+        self.ticks * NORMAL_SPEED as u64 / ACTION_COST as u64
+    }
+
+    //
+    // Called by the system acting for the current entity once it spent its
+    // action, the scheduler then hands the turn to the next ready entity.
+    //
+    pub fn end_turn(&mut self, actor: &mut TakesTurn, cost: u32) {
+        actor.energy = actor.energy.saturating_sub(cost);
+        self.current = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.ready.clear();
+    }
+
+    // Takes a dead entity out of the queue, whether it is acting or waiting.
+    pub fn remove(&mut self, entity: Entity) {
+        if self.current == Some(entity) {
+            self.current = None;
+        }
+        self.ready.retain(|ready| *ready != entity);
+    }
+
+    //
+    // Picks the next entity to act. When nobody is ready, time advances until
+    // someone has enough energy. Ready entities act from the most energetic to
+    // the least, ties being broken by entity id so the order only depends on
+    // the spawn order.
+    //
+    pub fn schedule(&mut self, actors: &mut [(Entity, Mut<TakesTurn>)]) {
+        if self.current.is_some() {
+            return;
+        }
+
+        while let Some(entity) = self.ready.pop_front() {
+            if actors.iter().any(|(actor, _)| *actor == entity) {
+                self.current = Some(entity);
+                return;
+            }
+        }
+
+        // Ticks needed by the fastest actor to be able to act.
+        let ticks = actors
+            .iter()
+            .filter(|(_, takes_turn)| takes_turn.speed > 0 || takes_turn.energy >= ACTION_COST)
+            .map(|(_, takes_turn)| {
+                if takes_turn.energy >= ACTION_COST {
+                    0
+                } else {
+                    let missing = ACTION_COST - takes_turn.energy;
+                    (missing + takes_turn.speed - 1) / takes_turn.speed
+                }
+            })
+            .min();
+
+        let ticks = match ticks {
+            Some(ticks) => ticks,
+            None => return,
+        };
+
+        for (_, takes_turn) in actors.iter_mut() {
+            takes_turn.energy += takes_turn.speed * ticks;
+        }
+        self.ticks += ticks as u64;
+
+        let mut ready: Vec<(u32, Entity)> = actors
+            .iter()
+            .filter(|(_, takes_turn)| takes_turn.energy >= ACTION_COST)
+            .map(|(entity, takes_turn)| (takes_turn.energy, *entity))
+            .collect();
+        ready.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.id().cmp(&b.1.id())));
+
+        self.ready = ready.into_iter().map(|(_, entity)| entity).collect();
+        self.current = self.ready.pop_front();
+    }
+}
+
+//
+// Hands the turn to the next actor. Nobody acts while a step is animated, so
+// the others see the tile the moving entity ends on, not the one it leaves.
+//
+pub fn turn_scheduler_system(
+    mut turn_queue: ResMut<TurnQueue>,
+    mut query: Query<(Entity, &mut TakesTurn)>,
+    animations: Query<(), With<StepAnimation>>,
+) {
+    if animations.iter().next().is_some() {
+        return;
+    }
+
+    // The entity whose turn it is may have been despawned meanwhile.
+    if let Some(current) = turn_queue.current {
+        if query.get_mut(current).is_ok() {
+            return;
+        }
+        turn_queue.current = None;
+    }
+
+    let mut actors: Vec<(Entity, Mut<TakesTurn>)> = query.iter_mut().collect();
+    turn_queue.schedule(&mut actors);
+}
+
+pub struct TurnPlugin;
+
+impl Plugin for TurnPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<TurnQueue>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(turn_scheduler_system.system().label(TurnSystem::Schedule)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .init_resource::<TurnQueue>()
+            .add_system(turn_scheduler_system.system());
+        app
+    }
+
+    fn spawn(app: &mut AppBuilder, speed: u32, energy: u32) -> Entity {
+        app.world_mut().spawn().insert(TakesTurn { speed, energy }).id()
+    }
+
+    // Runs the scheduler, lets every actor spend its action and returns who acted.
+    fn play(app: &mut AppBuilder, actions: usize) -> Vec<Entity> {
+        let mut order = Vec::new();
+        for _ in 0..actions {
+            app.app.update();
+            let world = &mut app.app.world;
+            let current = world.get_resource::<TurnQueue>().unwrap().current.expect("an actor is ready");
+            order.push(current);
+
+            let mut takes_turn = *world.get::<TakesTurn>(current).unwrap();
+            world
+                .get_resource_mut::<TurnQueue>()
+                .unwrap()
+                .end_turn(&mut takes_turn, ACTION_COST);
+            *world.get_mut::<TakesTurn>(current).unwrap() = takes_turn;
+        }
+        order
+    }
+
+    #[test]
+    fn faster_actors_act_more_often() {
+        let mut app = app();
+        let fast = spawn(&mut app, 2 * NORMAL_SPEED, 0);
+        let slow = spawn(&mut app, NORMAL_SPEED, 0);
+
+        let order = play(&mut app, 6);
+        assert_eq!(order, vec![fast, fast, slow, fast, fast, slow]);
+        assert_eq!(app.app.world.get_resource::<TurnQueue>().unwrap().ticks, 20);
+    }
+
+    #[test]
+    fn the_most_energetic_actor_acts_first() {
+        let mut app = app();
+        let first = spawn(&mut app, NORMAL_SPEED, 0);
+        let second = spawn(&mut app, NORMAL_SPEED, ACTION_COST / 2);
+
+        let order = play(&mut app, 4);
+        assert_eq!(order, vec![second, first, second, first]);
+    }
+
+    #[test]
+    fn ties_are_broken_by_spawn_order() {
+        let mut app = app();
+        let a = spawn(&mut app, NORMAL_SPEED, 0);
+        let b = spawn(&mut app, NORMAL_SPEED, 0);
+        let c = spawn(&mut app, NORMAL_SPEED, 0);
+
+        let order = play(&mut app, 9);
+        assert_eq!(order, vec![a, b, c, a, b, c, a, b, c]);
+    }
+
+    #[test]
+    fn the_order_is_the_same_on_every_run() {
+        let run = || {
+            let mut app = app();
+            spawn(&mut app, NORMAL_SPEED, 0);
+            spawn(&mut app, 2 * NORMAL_SPEED, 30);
+            spawn(&mut app, 3 * NORMAL_SPEED / 2, 60);
+            spawn(&mut app, NORMAL_SPEED, 90);
+            play(&mut app, 40)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn nobody_acts_while_a_step_is_animated() {
+        let mut app = app();
+        let a = spawn(&mut app, NORMAL_SPEED, 0);
+        let animated = app
+            .world_mut()
+            .spawn()
+            .insert(StepAnimation::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(32.0, 0.0, 0.0)))
+            .id();
+
+        app.app.update();
+        assert_eq!(app.app.world.get_resource::<TurnQueue>().unwrap().current, None);
+
+        app.app.world.entity_mut(animated).remove::<StepAnimation>();
+        app.app.update();
+        assert!(app.app.world.get_resource::<TurnQueue>().unwrap().is_turn_of(a));
+    }
+
+    #[test]
+    fn despawned_actors_lose_their_turn() {
+        let mut app = app();
+        let a = spawn(&mut app, NORMAL_SPEED, 0);
+        let b = spawn(&mut app, NORMAL_SPEED, 0);
+
+        app.app.update();
+        assert!(app.app.world.get_resource::<TurnQueue>().unwrap().is_turn_of(a));
+        app.app.world.despawn(a);
+
+        assert_eq!(play(&mut app, 2), vec![b, b]);
+    }
+
+    #[test]
+    fn removed_actors_leave_the_queue() {
+        let mut app = app();
+        let first = spawn(&mut app, NORMAL_SPEED, ACTION_COST);
+        let second = spawn(&mut app, NORMAL_SPEED, ACTION_COST);
+        app.app.update();
+
+        let mut turn_queue = app.world_mut().get_resource_mut::<TurnQueue>().unwrap();
+        let current = turn_queue.current.expect("an actor is ready");
+        let waiting = if current == first { second } else { first };
+        assert!(turn_queue.ready.contains(&waiting));
+
+        turn_queue.remove(waiting);
+        assert!(!turn_queue.ready.contains(&waiting));
+        assert_eq!(turn_queue.current, Some(current));
+
+        turn_queue.remove(current);
+        assert_eq!(turn_queue.current, None);
+    }
+}
@@ -0,0 +1,65 @@
+use bevy::prelude::*;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::env;
+
+pub const SEED_ARGUMENT: &str = "--seed";
+pub const SEED_ENV_VARIABLE: &str = "VOID_DESTINY_SEED";
+
+// Each consumer of the world seed draws from its own stream so adding random
+// draws somewhere doesn't change what is generated elsewhere.
+pub const MAP_GENERATION_STREAM: u64 = 0;
+pub const MONSTER_SPAWN_STREAM: u64 = 1;
+pub const AI_STREAM: u64 = 2;
+pub const COMBAT_STREAM: u64 = 3;
+pub const PLAYER_START_STREAM: u64 = 4;
+
+//
+// Seed from which every random value of a run is derived.
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+// Fixed so that apps built without a seed, like the tests, are reproducible.
+// The game reads its seed with WorldSeed::from_env.
+impl Default for WorldSeed {
+    fn default() -> Self {
+        WorldSeed(0)
+    }
+}
+
+impl WorldSeed {
+    //
+    // Reads the seed from the command line (--seed 1234 or --seed=1234), then
+    // from the VOID_DESTINY_SEED environment variable and falls back on a
+    // random seed.
+    //
+    pub fn from_env() -> WorldSeed {
+        let args: Vec<String> = env::args().collect();
+        let prefix = format!("{}=", SEED_ARGUMENT);
+        let argument = args.iter().enumerate().find_map(|(i, arg)| {
+            if arg == SEED_ARGUMENT {
+                args.get(i + 1).cloned()
+            } else {
+                arg.strip_prefix(prefix.as_str()).map(|value| value.to_string())
+            }
+        });
+
+        let value = argument.or_else(|| env::var(SEED_ENV_VARIABLE).ok());
+        if let Some(value) = value {
+            match value.trim().parse::<u64>() {
+                Ok(seed) => return WorldSeed(seed),
+                Err(_) => warn!("Invalid world seed '{}', using a random one.", value),
+            }
+        }
+
+        WorldSeed(rand::thread_rng().gen())
+    }
+
+    pub fn rng(&self, stream: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.0);
+        rng.set_stream(stream);
+        rng
+    }
+}